@@ -0,0 +1,369 @@
+//! # Async module
+//! The `Execute` API for async programs, behind the `async` cargo feature.
+//! Commands are spawned with `tokio::process` and awaited without blocking a
+//! worker thread, the specs, outputs and errors are the ones of the sync API:
+//! `CommandSpec` gets `*_async` twins of its run methods, `Batch` a `run_async`
+//! and `AsyncExecute` mirrors the shortcuts of `Execute`.
+//!
+//! Timeouts, cancel handles, retries, stdin sources, `merge_stderr`, `pty` and the
+//! dry-run mode behave the same way. The futures must run inside a tokio runtime.
+
+use crate::batch::{Batch, BatchOutput};
+use crate::error::CommandError;
+use crate::execute::{CommandSpec, Input, OutputEvent, Stream};
+use crate::output::ExecOutput;
+use crate::process::{Chunk, ChunkSink, Collected, Lines, Pipe, Spawned, POLL_INTERVAL};
+use crate::retry::{self, Next};
+use crate::shell::Shell;
+use std::borrow::Cow;
+use std::process::ExitStatus;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// A pipe of a tokio child as a blocking `File`, to be drained or fed on a thread.
+macro_rules! blocking {
+    ($pipe:expr) => {
+        match $pipe {
+            #[cfg(unix)]
+            Some(pipe) => Some(std::fs::File::from(pipe.into_owned_fd()?)),
+            #[cfg(windows)]
+            Some(pipe) => Some(std::fs::File::from(pipe.into_owned_handle()?)),
+            None => None,
+        }
+    };
+}
+
+/// # AsyncExecute
+/// The shortcuts of `Execute`, awaited instead of blocking.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::async_execute::AsyncExecute;
+///
+/// # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+/// let out = AsyncExecute::run("echo", &["hello"]).await.unwrap();
+/// assert_eq!(out.stdout_str(), "hello\n");
+/// let out = AsyncExecute::run_parallel(&[("echo", &["a"]), ("false", &[])], 2).await;
+/// assert_eq!((out.succeeded(), out.failed()), (1, 1));
+/// # });
+/// ```
+pub struct AsyncExecute;
+
+impl AsyncExecute {
+    /// Execute `command` with `arguments` and return its output, like `Execute::run`.
+    pub async fn run(command: &str, arguments: &[&str]) -> Result<ExecOutput, CommandError> {
+        CommandSpec::new(command)
+            .args(arguments.iter().copied())
+            .run_async()
+            .await
+    }
+
+    /// Execute `command` with stdout inherited and return its exit status, like `Execute::exe`.
+    pub async fn exe(command: &str, arguments: &[&str]) -> Result<ExitStatus, CommandError> {
+        CommandSpec::new(command)
+            .args(arguments.iter().copied())
+            .stderr(Stream::Inherit)
+            .exe_async()
+            .await
+    }
+
+    /// Like `exe`, but fails when the command doesn't succeed, like `Execute::exe_checked`.
+    pub async fn exe_checked(
+        command: &str,
+        arguments: &[&str],
+    ) -> Result<ExitStatus, CommandError> {
+        CommandSpec::new(command)
+            .args(arguments.iter().copied())
+            .exe_checked_async()
+            .await
+    }
+
+    /// Split `line` like a POSIX shell would and execute it, like `Execute::run_str`.
+    pub async fn run_str(line: &str) -> Result<ExecOutput, CommandError> {
+        CommandSpec::parse(line)?.run_async().await
+    }
+
+    /// Run `script` through `sh -c` with `arguments` as `$1`, `$2`..., like `Execute::shell`.
+    pub async fn shell(script: &str, arguments: &[&str]) -> Result<ExecOutput, CommandError> {
+        Shell::default()
+            .command(script)
+            .args(arguments.iter().copied())
+            .run_async()
+            .await
+    }
+
+    /// Execute several commands at the same time, at most `limit` at once, like `Execute::run_parallel`.
+    pub async fn run_parallel(commands: &[(&str, &[&str])], limit: usize) -> BatchOutput {
+        Batch::new()
+            .limit(limit)
+            .extend(commands.iter().map(|(command, arguments)| {
+                CommandSpec::new(*command).args(arguments.iter().copied())
+            }))
+            .run_async()
+            .await
+    }
+
+    /// Execute the command and call `on_event` for every line it prints, like `Execute::stream`.
+    pub async fn stream<F>(
+        command: &str,
+        arguments: &[&str],
+        on_event: F,
+    ) -> Result<ExecOutput, CommandError>
+    where
+        F: FnMut(&OutputEvent),
+    {
+        CommandSpec::new(command)
+            .args(arguments.iter().copied())
+            .stream_async(on_event)
+            .await
+    }
+}
+
+impl CommandSpec {
+    /// Execute the command and return everything about it whatever its exit status,
+    /// like `output`.
+    pub async fn output_async(&self) -> Result<ExecOutput, CommandError> {
+        self.drive(|_, _| {}).await
+    }
+
+    /// Execute the command and return its output, failing like `run` does
+    /// (retry policy included).
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::{CommandError, CommandSpec, Input};
+    /// use std::time::Duration;
+    ///
+    /// # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+    /// let out = CommandSpec::new("tr")
+    ///     .args(["a-z", "A-Z"])
+    ///     .stdin(Input::Bytes(b"shout".to_vec()))
+    ///     .run_async()
+    ///     .await
+    ///     .unwrap();
+    /// assert_eq!(out.stdout, b"SHOUT");
+    ///
+    /// let res = CommandSpec::new("sleep")
+    ///     .arg("10")
+    ///     .timeout(Duration::from_millis(100))
+    ///     .run_async()
+    ///     .await;
+    /// assert!(matches!(res, Err(CommandError::Timeout { .. })));
+    /// # });
+    /// ```
+    pub async fn run_async(&self) -> Result<ExecOutput, CommandError> {
+        let Some(policy) = self.get_retry() else {
+            return CommandError::check(self.output_async().await?);
+        };
+        let mut attempts = Vec::new();
+        for number in 1.. {
+            let result = self.output_async().await;
+            match policy.next(number, result, &mut attempts) {
+                Next::Done(result) => return result,
+                Next::Retry(delay) => {
+                    let cancel = self.get_cancel_handles();
+                    let until = tokio::time::Instant::now() + delay;
+                    while !retry::is_cancelled(cancel) && tokio::time::Instant::now() < until {
+                        tokio::time::sleep_until(
+                            until.min(tokio::time::Instant::now() + POLL_INTERVAL),
+                        )
+                        .await;
+                    }
+                    if retry::is_cancelled(cancel) {
+                        return Err(retry::cancelled(attempts));
+                    }
+                }
+            }
+        }
+        unreachable!("the loop only ends by returning")
+    }
+
+    /// Execute the command and call `on_event` for every line of stdout and stderr
+    /// as soon as it is printed, like `stream`.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::{CommandSpec, OutputEvent};
+    ///
+    /// # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+    /// let mut lines = Vec::new();
+    /// let out = CommandSpec::new("sh")
+    ///     .args(["-c", "echo building; echo warning >&2; printf done"])
+    ///     .stream_async(|event| lines.push(event.clone()))
+    ///     .await
+    ///     .unwrap();
+    /// assert!(lines.contains(&OutputEvent::Stderr("warning".into())));
+    /// assert_eq!(lines.last(), Some(&OutputEvent::Exit(out.status)));
+    /// # });
+    /// ```
+    pub async fn stream_async<F>(&self, mut on_event: F) -> Result<ExecOutput, CommandError>
+    where
+        F: FnMut(&OutputEvent),
+    {
+        let mut lines = Lines::default();
+        let result = self
+            .captured()
+            .drive(|pipe, bytes| lines.split(pipe, bytes).iter().for_each(&mut on_event))
+            .await;
+        lines.flush().iter().for_each(&mut on_event);
+        let output = match &result {
+            Ok(output) => Some(output),
+            Err(err) => err.output(),
+        };
+        if let Some(output) = output {
+            on_event(&OutputEvent::Exit(output.status));
+        }
+        CommandError::check(result?)
+    }
+
+    /// Execute the command with stdout inherited and return its exit status, like `exe`.
+    pub async fn exe_async(&self) -> Result<ExitStatus, CommandError> {
+        Ok(self
+            .clone()
+            .stdout(Stream::Inherit)
+            .output_async()
+            .await?
+            .status)
+    }
+
+    /// Like `exe_async`, but fails when the command doesn't succeed, like `exe_checked`.
+    pub async fn exe_checked_async(&self) -> Result<ExitStatus, CommandError> {
+        Ok(self
+            .clone()
+            .stdout(Stream::Inherit)
+            .run_async()
+            .await?
+            .status)
+    }
+
+    /// spawn the command on tokio and collect its output, handing every chunk to `on_chunk`
+    async fn drive<F>(&self, mut on_chunk: F) -> Result<ExecOutput, CommandError>
+    where
+        F: FnMut(Pipe, &[u8]),
+    {
+        if self.get_dry_run() {
+            return Ok(self.dry_output());
+        }
+        // the upstream command is awaited here, `launch` would block on it
+        let spec = match self.get_stdin() {
+            Input::Command(upstream) => {
+                let upstream = Box::pin(upstream.run_async()).await?;
+                Cow::Owned(self.clone().stdin(Input::Bytes(upstream.stdout)))
+            }
+            _ => Cow::Borrowed(self),
+        };
+        let (mut child, spawned) = spec.launch()?.spawn_with(|cmd| {
+            let mut cmd = tokio::process::Command::from(cmd);
+            cmd.kill_on_drop(true);
+            cmd.spawn()
+        })?;
+        let Spawned {
+            command,
+            input,
+            merged,
+            mut watchdog,
+        } = spawned;
+        let pid = child.id().unwrap_or_default();
+
+        // the pipes are drained and fed on threads like the sync API does it
+        let stdin = match input {
+            Some(feed) => blocking!(child.stdin.take()).map(|stdin| (feed, stdin)),
+            None => None,
+        };
+        let stdout = blocking!(child.stdout.take());
+        let stderr = blocking!(child.stderr.take());
+        let (tx, mut chunks) = mpsc::unbounded_channel();
+        let mut output = Collected::wire(stdout, stderr, merged, stdin, tx)?;
+
+        let mut status = None;
+        while status.is_none() || !output.is_closed() {
+            let mut signal = None;
+            tokio::select! {
+                chunk = chunks.recv(), if !output.is_closed() => {
+                    let chunk = chunk.unwrap_or(Chunk::Closed);
+                    if let Some((pipe, bytes)) = output.record(chunk) {
+                        on_chunk(pipe, &bytes);
+                    }
+                }
+                exited = child.wait(), if status.is_none() => status = Some(exited?),
+                _ = tokio::time::sleep(POLL_INTERVAL), if watchdog.is_watched() => {
+                    signal = watchdog.check();
+                }
+            }
+            if let Some(kill) = signal {
+                stop(&mut child, pid, kill);
+            }
+        }
+        let status = status.expect("the loop only ends once the command exited");
+        output.finish(command, pid, status, &watchdog)
+    }
+}
+
+impl Batch {
+    /// Execute every command and wait for all of them, like `run`, as tasks of the
+    /// current tokio runtime instead of threads.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::batch::Batch;
+    /// use commandcrafter::execute::{CommandError, CommandSpec};
+    ///
+    /// # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+    /// let out = Batch::new()
+    ///     .limit(1)
+    ///     .fail_fast(true)
+    ///     .push(CommandSpec::new("false"))
+    ///     .push(CommandSpec::new("echo").arg("never"))
+    ///     .run_async()
+    ///     .await;
+    /// assert!(matches!(out.results[1], Err(CommandError::Skipped { .. })));
+    /// # });
+    /// ```
+    pub async fn run_async(&self) -> BatchOutput {
+        let started = Instant::now();
+        let permits = Arc::new(Semaphore::new(self.limit));
+        let fail_fast = self.fail_fast_handle();
+        let mut tasks = JoinSet::new();
+        for (i, spec) in self.specs.iter().enumerate() {
+            let spec = fail_fast.watch(spec).into_owned();
+            let (permits, fail_fast) = (permits.clone(), fail_fast.clone());
+            tasks.spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                if fail_fast.is_cancelled() {
+                    return (i, None);
+                }
+                let result = spec.run_async().await;
+                fail_fast.settle(&result);
+                (i, Some(result))
+            });
+        }
+
+        let mut slots: Vec<Option<Result<ExecOutput, CommandError>>> =
+            self.specs.iter().map(|_| None).collect();
+        while let Some(joined) = tasks.join_next().await {
+            if let Ok((i, result)) = joined {
+                slots[i] = result;
+            }
+        }
+        self.assemble(slots, started)
+    }
+}
+
+impl ChunkSink for UnboundedSender<Chunk> {
+    fn send_chunk(&self, chunk: Chunk) -> bool {
+        self.send(chunk).is_ok()
+    }
+}
+
+#[cfg(unix)]
+fn stop(_child: &mut tokio::process::Child, pid: u32, kill: bool) {
+    crate::process::signal_group(pid, kill);
+}
+
+#[cfg(not(unix))]
+fn stop(child: &mut tokio::process::Child, _pid: u32, _kill: bool) {
+    let _ = child.start_kill();
+}
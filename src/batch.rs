@@ -0,0 +1,227 @@
+//! # Batch module
+//! Run many commands at once on a bounded pool of worker threads, the results
+//! come back in the order the commands were given, whatever order they finished in.
+
+use crate::error::CommandError;
+use crate::execute::{CancelHandle, CommandSpec};
+use crate::output::ExecOutput;
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// # Batch
+/// A set of commands executed in parallel, at most `limit` at a time.
+///
+/// By default every command runs whatever happens to the others (collect-all),
+/// with `fail_fast` the first failure cancels the running commands and skips the rest.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::batch::Batch;
+/// use commandcrafter::execute::CommandSpec;
+///
+/// let out = Batch::new()
+///     .limit(2)
+///     .push(CommandSpec::new("sh").args(["-c", "sleep 0.2; echo slow"]))
+///     .push(CommandSpec::new("echo").arg("fast"))
+///     .push(CommandSpec::new("false"))
+///     .run();
+/// assert_eq!(out.results[0].as_ref().unwrap().stdout_str(), "slow\n");
+/// assert_eq!(out.results[1].as_ref().unwrap().stdout_str(), "fast\n");
+/// assert!(out.results[2].is_err());
+/// assert_eq!((out.succeeded(), out.failed()), (2, 1));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Batch {
+    pub(crate) specs: Vec<CommandSpec>,
+    pub(crate) limit: usize,
+    pub(crate) fail_fast: bool,
+}
+
+/// What a batch produced.
+#[derive(Debug)]
+pub struct BatchOutput {
+    /// one result per command, in the order they were pushed
+    pub results: Vec<Result<ExecOutput, CommandError>>,
+    /// the wall-clock time the whole batch took
+    pub elapsed: Duration,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Batch::new()
+    }
+}
+
+impl Batch {
+    /// An empty batch running as many commands at a time as there are CPUs.
+    pub fn new() -> Self {
+        Batch {
+            specs: Vec::new(),
+            limit: thread::available_parallelism().map_or(4, |n| n.get()),
+            fail_fast: false,
+        }
+    }
+
+    /// Add a command to the batch.
+    pub fn push(mut self, spec: CommandSpec) -> Self {
+        self.specs.push(spec);
+        self
+    }
+
+    /// Add several commands to the batch.
+    pub fn extend<I: IntoIterator<Item = CommandSpec>>(mut self, specs: I) -> Self {
+        self.specs.extend(specs);
+        self
+    }
+
+    /// Run at most `limit` commands at the same time (at least one).
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit.max(1);
+        self
+    }
+
+    /// Stop at the first failure: the commands still running are cancelled
+    /// (`CommandError::Cancelled`) and the ones not started yet are skipped
+    /// (`CommandError::Skipped`).
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::batch::Batch;
+    /// use commandcrafter::execute::{CommandError, CommandSpec};
+    ///
+    /// let out = Batch::new()
+    ///     .limit(1)
+    ///     .fail_fast(true)
+    ///     .push(CommandSpec::new("false"))
+    ///     .push(CommandSpec::new("echo").arg("never"))
+    ///     .run();
+    /// assert!(matches!(out.results[1], Err(CommandError::Skipped { .. })));
+    /// ```
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Execute every command and wait for all of them, each is checked like `Execute::run`.
+    pub fn run(&self) -> BatchOutput {
+        let started = Instant::now();
+        let next = AtomicUsize::new(0);
+        let fail_fast = self.fail_fast_handle();
+        let slots: Mutex<Vec<Option<Result<ExecOutput, CommandError>>>> =
+            Mutex::new(self.specs.iter().map(|_| None).collect());
+
+        thread::scope(|scope| {
+            for _ in 0..self.limit.min(self.specs.len()) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(spec) = self.specs.get(i) else {
+                        break;
+                    };
+                    if fail_fast.is_cancelled() {
+                        break;
+                    }
+                    let result = fail_fast.watch(spec).run();
+                    fail_fast.settle(&result);
+                    slots.lock().unwrap()[i] = Some(result);
+                });
+            }
+        });
+
+        self.assemble(slots.into_inner().unwrap(), started)
+    }
+
+    /// what stops the rest of the batch once a command failed
+    pub(crate) fn fail_fast_handle(&self) -> FailFast {
+        FailFast {
+            enabled: self.fail_fast,
+            cancel: CancelHandle::new(),
+        }
+    }
+
+    /// the output of the batch, the commands without a result in `slots` were skipped
+    pub(crate) fn assemble(
+        &self,
+        slots: Vec<Option<Result<ExecOutput, CommandError>>>,
+        started: Instant,
+    ) -> BatchOutput {
+        let results = slots
+            .into_iter()
+            .zip(&self.specs)
+            .map(|(slot, spec)| {
+                slot.unwrap_or_else(|| {
+                    Err(CommandError::Skipped {
+                        program: spec.get_program().to_string(),
+                    })
+                })
+            })
+            .collect();
+        BatchOutput {
+            results,
+            elapsed: started.elapsed(),
+        }
+    }
+}
+
+/// The handle a batch cancels its remaining commands with, when failing fast.
+#[derive(Debug, Clone)]
+pub(crate) struct FailFast {
+    enabled: bool,
+    cancel: CancelHandle,
+}
+
+impl FailFast {
+    /// `spec` as a member of the batch, stopped by the handle when failing fast
+    pub fn watch<'a>(&self, spec: &'a CommandSpec) -> Cow<'a, CommandSpec> {
+        if self.enabled {
+            Cow::Owned(spec.clone().cancel_on(&self.cancel))
+        } else {
+            Cow::Borrowed(spec)
+        }
+    }
+
+    /// cancel the rest of the batch when failing fast and `result` is a failure
+    pub fn settle(&self, result: &Result<ExecOutput, CommandError>) {
+        // a command cancelled because of another failure isn't a failure of its own
+        if self.enabled && result.is_err() && !self.cancel.is_cancelled() {
+            self.cancel.cancel();
+        }
+    }
+
+    /// `true` once the commands that didn't start yet must be skipped
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+}
+
+impl BatchOutput {
+    /// How many commands succeeded.
+    pub fn succeeded(&self) -> usize {
+        self.results.iter().filter(|r| r.is_ok()).count()
+    }
+
+    /// How many commands failed, were cancelled or skipped.
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.succeeded()
+    }
+
+    /// `true` when every command succeeded.
+    pub fn all_succeeded(&self) -> bool {
+        self.failed() == 0
+    }
+
+    /// The time the commands spent running added together, compare it with
+    /// `elapsed` to see how much the parallelism saved.
+    pub fn busy(&self) -> Duration {
+        self.results
+            .iter()
+            .filter_map(|r| match r {
+                Ok(out) => Some(out),
+                Err(e) => e.output(),
+            })
+            .map(|out| out.duration)
+            .sum()
+    }
+}
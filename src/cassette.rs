@@ -0,0 +1,270 @@
+//! # Cassette module
+//! Record the commands a program really runs (arguments, explicit environment,
+//! stdout, stderr, status and timing) into a JSON fixture file, then replay them
+//! later without executing anything, so parsing code can be tested offline
+//! against real output (`pacman -Qu`, `yay -Qu`...).
+
+use crate::error::CommandError;
+use crate::execute::{CommandSpec, Execute};
+use crate::output::{raw_status, ExecOutput};
+use crate::runner::Runner;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// What a `Cassette` does with the commands it is asked to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// run every command for real and record it, replacing what the file held
+    Record,
+    /// never run anything, a command that wasn't recorded is an error
+    Replay,
+    /// replay the commands that were recorded, run and record the others
+    RecordIfMissing,
+}
+
+/// A recorded command and what it produced.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Recording {
+    /// the program that was executed
+    pub program: String,
+    /// its arguments
+    pub args: Vec<String>,
+    /// the working directory, when one was set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    /// the environment variables set (or removed, `null`) on the spec, not the whole environment
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, Option<String>>,
+    /// the standard output
+    pub stdout: Bytes,
+    /// the standard error
+    pub stderr: Bytes,
+    /// the exit code, `null` when the command was killed by a signal
+    pub code: Option<i32>,
+    /// the signal that killed the command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    /// how long the command took, in milliseconds
+    pub duration_ms: u64,
+}
+
+/// Output bytes as stored in a fixture: text when it is valid UTF-8, a list of bytes otherwise.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Bytes {
+    /// the output as text
+    Text(String),
+    /// the output as raw bytes
+    Raw(Vec<u8>),
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Bytes::Text(text.to_string()),
+            Err(_) => Bytes::Raw(bytes.to_vec()),
+        }
+    }
+}
+
+impl Bytes {
+    /// The stored bytes.
+    pub fn to_vec(&self) -> Vec<u8> {
+        match self {
+            Bytes::Text(text) => text.clone().into_bytes(),
+            Bytes::Raw(bytes) => bytes.clone(),
+        }
+    }
+}
+
+impl Recording {
+    /// Record what `spec` produced.
+    pub fn new(spec: &CommandSpec, output: &ExecOutput) -> Self {
+        Recording {
+            program: spec.get_program().to_string(),
+            args: spec.get_args().to_vec(),
+            cwd: spec.get_cwd().map(Path::to_path_buf),
+            env: spec.get_envs().iter().cloned().collect(),
+            stdout: Bytes::from(output.stdout.as_slice()),
+            stderr: Bytes::from(output.stderr.as_slice()),
+            code: output.code(),
+            signal: output.signal,
+            duration_ms: output.duration.as_millis() as u64,
+        }
+    }
+
+    /// `true` when this is a recording of `spec`: same program, arguments, directory and environment.
+    pub fn matches(&self, spec: &CommandSpec) -> bool {
+        self.program == spec.get_program()
+            && self.args == spec.get_args()
+            && self.cwd.as_deref() == spec.get_cwd()
+            && self.env == spec.get_envs().iter().cloned().collect()
+    }
+
+    /// The output the recorded command produced, as if it ran again.
+    pub fn to_output(&self, spec: &CommandSpec) -> ExecOutput {
+        ExecOutput::new(
+            spec.command_line(),
+            0,
+            raw_status(self.code, self.signal),
+            self.stdout.to_vec(),
+            self.stderr.to_vec(),
+            Duration::from_millis(self.duration_ms),
+        )
+    }
+}
+
+/// the content of a fixture file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Fixture {
+    calls: Vec<Recording>,
+}
+
+#[derive(Debug)]
+struct Tape {
+    recordings: Vec<Recording>,
+    /// how many times each recording was replayed
+    played: Vec<usize>,
+}
+
+/// # Cassette
+/// A `Runner` recording the commands it runs into a fixture file, or replaying them from it.
+///
+/// Recorded calls are matched on program, arguments, working directory and the
+/// environment set on the spec. When a command was recorded several times the
+/// recordings are replayed in order, the last one being repeated afterwards.
+/// Only commands that ran until the end are recorded, spawn errors and timeouts
+/// are returned without being stored.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::cassette::{Cassette, CassetteMode};
+/// use commandcrafter::runner::Runner;
+///
+/// let fixture = std::env::temp_dir().join(format!("cassette-doc-{}.json", std::process::id()));
+/// let _ = std::fs::remove_file(&fixture);
+///
+/// // the first time the command runs for real and is recorded
+/// let cassette = Cassette::open(&fixture, CassetteMode::RecordIfMissing).unwrap();
+/// let live = cassette.run_args("sh", &["-c", "echo \"updates: $$\""]).unwrap();
+///
+/// // later on it is replayed, nothing is executed
+/// let cassette = Cassette::open(&fixture, CassetteMode::Replay).unwrap();
+/// let replayed = cassette.run_args("sh", &["-c", "echo \"updates: $$\""]).unwrap();
+/// assert_eq!(replayed.stdout, live.stdout);
+/// assert!(cassette.run_args("sh", &["-c", "something else"]).is_err());
+/// let _ = std::fs::remove_file(&fixture);
+/// ```
+#[derive(Debug)]
+pub struct Cassette<R: Runner = Execute> {
+    path: PathBuf,
+    mode: CassetteMode,
+    runner: R,
+    tape: Mutex<Tape>,
+}
+
+impl Cassette<Execute> {
+    /// A cassette stored at `path` running the commands it records with `Execute`.
+    ///
+    /// The file is read unless the mode is `Record`, a missing file is an empty cassette.
+    pub fn open(path: impl Into<PathBuf>, mode: CassetteMode) -> Result<Self, CommandError> {
+        Cassette::with_runner(path, mode, Execute)
+    }
+}
+
+impl<R: Runner> Cassette<R> {
+    /// A cassette stored at `path` running the commands it records with `runner`.
+    pub fn with_runner(
+        path: impl Into<PathBuf>,
+        mode: CassetteMode,
+        runner: R,
+    ) -> Result<Self, CommandError> {
+        let path = path.into();
+        let fixture = match (mode, fs::read_to_string(&path)) {
+            (CassetteMode::Record, _) => Fixture::default(),
+            (_, Ok(content)) => serde_json::from_str(&content).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid fixture '{}': {}", path.display(), e),
+                )
+            })?,
+            (_, Err(e)) if e.kind() == io::ErrorKind::NotFound => Fixture::default(),
+            (_, Err(e)) => return Err(e.into()),
+        };
+        let played = vec![0; fixture.calls.len()];
+        Ok(Cassette {
+            path,
+            mode,
+            runner,
+            tape: Mutex::new(Tape {
+                recordings: fixture.calls,
+                played,
+            }),
+        })
+    }
+
+    /// The file the recordings are stored in.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every recording of the cassette, in the order they were made.
+    pub fn recordings(&self) -> Vec<Recording> {
+        self.tape.lock().unwrap().recordings.clone()
+    }
+
+    /// write the recordings into the fixture file
+    fn save(&self, tape: &Tape) -> Result<(), CommandError> {
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let fixture = Fixture {
+            calls: tape.recordings.clone(),
+        };
+        let json = serde_json::to_string_pretty(&fixture).map_err(io::Error::other)?;
+        fs::write(&self.path, json + "\n")?;
+        Ok(())
+    }
+}
+
+impl<R: Runner> Runner for Cassette<R> {
+    fn output(&self, spec: &CommandSpec) -> Result<ExecOutput, CommandError> {
+        if self.mode != CassetteMode::Record {
+            let mut tape = self.tape.lock().unwrap();
+            let Tape { recordings, played } = &mut *tape;
+            let matching: Vec<usize> = (0..recordings.len())
+                .filter(|&i| recordings[i].matches(spec))
+                .collect();
+            let next = matching
+                .iter()
+                .find(|&&i| played[i] == 0)
+                .or(matching.last());
+            if let Some(&i) = next {
+                played[i] += 1;
+                return Ok(recordings[i].to_output(spec));
+            }
+        }
+        if self.mode == CassetteMode::Replay {
+            return Err(CommandError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "no recording of '{}' in '{}'",
+                    spec.command_line(),
+                    self.path.display()
+                ),
+            )));
+        }
+
+        let output = self.runner.output(spec)?;
+        let mut tape = self.tape.lock().unwrap();
+        tape.recordings.push(Recording::new(spec, &output));
+        tape.played.push(1);
+        self.save(&tape)?;
+        Ok(output)
+    }
+}
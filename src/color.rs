@@ -1,6 +1,27 @@
 #![allow(dead_code)]
 //! this Module intends to colorize the output of ongoing process
 
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Turn the colors on or off for the whole program, when off `print_col` returns the text as is.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::color::{self, Col};
+/// color::set_enabled(false);
+/// assert_eq!(Col::Red.print_col("plain"), "plain");
+/// ```
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// `true` unless the colors were turned off with `set_enabled`.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
 /// enum Col
 pub enum Col {
     Green,
@@ -31,6 +52,9 @@ impl Col {
     /// assert_eq!(text2, "\x1b[32mhello\x1b[0m");
     /// ```
     pub fn print_col(&self, text: &str) -> String {
+        if !is_enabled() {
+            return text.to_string();
+        }
         match self {
             Col::Green => format!("\x1b[32m{}\x1b[0m", text),
             Col::Red => format!("\x1b[31m{}\x1b[0m", text),
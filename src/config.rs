@@ -0,0 +1,286 @@
+//! # Config module
+//! The task file of the `commandcrafter` binary (`Crafter.toml`): named commands
+//! with their arguments, environment, working directory, timeout and log file,
+//! so new jobs can be added without recompiling anything.
+//!
+//! ```toml
+//! [tasks.sync]
+//! command = "sudo pacman -Sy"
+//!
+//! [tasks.update-check]
+//! description = "list the packages that can be upgraded"
+//! command = "pacman"
+//! args = ["-Qu", "--color=always"]
+//! env = { LANG = "C" }
+//! cwd = "/"
+//! timeout = "30s"
+//! log = "update-check.log"
+//! log_dir = "/var/log/updates"
+//! depends_on = ["sync"]
+//! ```
+
+use crate::execute::CommandSpec;
+use crate::filestore::Filestore;
+use crate::tasks::{GraphError, Task, TaskGraph};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// the file looked up in the current directory when no path is given
+pub const DEFAULT_FILE: &str = "Crafter.toml";
+/// the environment variable pointing to the task file, takes precedence over `DEFAULT_FILE`
+pub const CONFIG_ENV: &str = "COMMANDCRAFTER_CONFIG";
+
+/// Why a task file couldn't be used.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// the file couldn't be read
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// the file isn't valid TOML or doesn't have the expected shape
+    Parse(toml::de::Error),
+    /// no task has this name
+    UnknownTask(String),
+    /// a task is described in a way that can't be executed
+    InvalidTask { task: String, reason: String },
+    /// the dependencies between the tasks are broken
+    Graph(GraphError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => {
+                write!(f, "couldn't read '{}': {}", path.display(), source)
+            }
+            ConfigError::Parse(err) => write!(f, "invalid task file: {}", err),
+            ConfigError::UnknownTask(name) => write!(f, "no task named '{}'", name),
+            ConfigError::InvalidTask { task, reason } => {
+                write!(f, "invalid task '{}': {}", task, reason)
+            }
+            ConfigError::Graph(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            ConfigError::Parse(err) => Some(err),
+            ConfigError::Graph(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<GraphError> for ConfigError {
+    fn from(err: GraphError) -> Self {
+        ConfigError::Graph(err)
+    }
+}
+
+/// The content of a task file.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CrafterConfig {
+    /// the tasks by name
+    #[serde(default)]
+    pub tasks: BTreeMap<String, TaskConfig>,
+}
+
+/// One named command of the task file.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TaskConfig {
+    /// what the task is for, shown when listing the tasks
+    pub description: Option<String>,
+    /// the command line, split like a POSIX shell would (see `shellwords::split`)
+    pub command: String,
+    /// more arguments appended after the ones found in `command`
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// environment variables set for the command
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// the directory the command runs in
+    pub cwd: Option<PathBuf>,
+    /// how long the command may run, as seconds or text like `"500ms"`, `"30s"`, `"5m"`, `"1h"`
+    pub timeout: Option<TimeoutValue>,
+    /// the log file the output of the command is written into
+    pub log: Option<String>,
+    /// the folder `log` is stored in, instead of `Filestore::log_dir`
+    pub log_dir: Option<PathBuf>,
+    /// the tasks that have to succeed before this one starts
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+}
+
+/// A timeout as written in a task file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum TimeoutValue {
+    /// a number of seconds
+    Seconds(u64),
+    /// a number followed by `ms`, `s`, `m` or `h`
+    Text(String),
+}
+
+impl TimeoutValue {
+    /// The duration this value stands for.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::config::TimeoutValue;
+    /// use std::time::Duration;
+    ///
+    /// assert_eq!(TimeoutValue::Seconds(3).to_duration(), Ok(Duration::from_secs(3)));
+    /// assert_eq!(TimeoutValue::Text("250ms".into()).to_duration(), Ok(Duration::from_millis(250)));
+    /// assert_eq!(TimeoutValue::Text("2m".into()).to_duration(), Ok(Duration::from_secs(120)));
+    /// assert!(TimeoutValue::Text("soon".into()).to_duration().is_err());
+    /// assert!(TimeoutValue::Text("99999999999999999999999s".into()).to_duration().is_err());
+    /// ```
+    pub fn to_duration(&self) -> Result<Duration, String> {
+        match self {
+            TimeoutValue::Seconds(secs) => Ok(Duration::from_secs(*secs)),
+            TimeoutValue::Text(text) => parse_duration(text),
+        }
+    }
+}
+
+/// Parse a duration like `"500ms"`, `"30s"`, `"5m"`, `"1h"` or a bare number of seconds.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (value, unit) = text.split_at(split);
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("'{}' is not a duration", text))?;
+    let secs = match unit.trim() {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        other => return Err(format!("unknown duration unit '{}' in '{}'", other, text)),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("'{}' is too long a duration", text))
+}
+
+impl CrafterConfig {
+    /// Parse a task file from its content.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::config::CrafterConfig;
+    ///
+    /// let config = CrafterConfig::parse(r#"
+    ///     [tasks.hello]
+    ///     command = "echo 'hello world'"
+    ///     timeout = "5s"
+    /// "#).unwrap();
+    /// let spec = config.spec("hello").unwrap();
+    /// assert_eq!(spec.get_args(), ["hello world"]);
+    /// ```
+    pub fn parse(content: &str) -> Result<Self, ConfigError> {
+        toml::from_str(content).map_err(ConfigError::Parse)
+    }
+
+    /// Read and parse the task file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        CrafterConfig::parse(&content)
+    }
+
+    /// The task file to use: `$COMMANDCRAFTER_CONFIG` when set, `Crafter.toml` otherwise.
+    pub fn default_path() -> PathBuf {
+        std::env::var_os(CONFIG_ENV)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_FILE))
+    }
+
+    /// The task named `name`.
+    pub fn task(&self, name: &str) -> Result<&TaskConfig, ConfigError> {
+        self.tasks
+            .get(name)
+            .ok_or_else(|| ConfigError::UnknownTask(name.to_string()))
+    }
+
+    /// The command the task named `name` runs.
+    pub fn spec(&self, name: &str) -> Result<CommandSpec, ConfigError> {
+        self.task(name)?.to_spec(name)
+    }
+
+    /// A graph with the task named `name` and everything it depends on, directly or not.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::config::CrafterConfig;
+    ///
+    /// let config = CrafterConfig::parse(r#"
+    ///     [tasks.fetch]
+    ///     command = "echo fetching"
+    ///     [tasks.build]
+    ///     command = "echo building"
+    ///     depends_on = ["fetch"]
+    ///     [tasks.unrelated]
+    ///     command = "false"
+    /// "#).unwrap();
+    /// let report = config.graph("build").unwrap().run().unwrap();
+    /// assert!(report.all_succeeded());
+    /// assert_eq!(report.results.len(), 2);
+    /// ```
+    pub fn graph(&self, name: &str) -> Result<TaskGraph, ConfigError> {
+        let mut graph = TaskGraph::new();
+        let mut wanted = vec![name.to_string()];
+        let mut seen = std::collections::BTreeSet::new();
+        while let Some(name) = wanted.pop() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let task = self.task(&name)?;
+            wanted.extend(task.depends_on.iter().cloned());
+            graph = graph.push(Task::new(&name, task.to_spec(&name)?).depends_on(&task.depends_on));
+        }
+        graph.validate()?;
+        Ok(graph)
+    }
+}
+
+impl TaskConfig {
+    /// The command this task runs, `name` is only used in error messages.
+    pub fn to_spec(&self, name: &str) -> Result<CommandSpec, ConfigError> {
+        let invalid = |reason: String| ConfigError::InvalidTask {
+            task: name.to_string(),
+            reason,
+        };
+        let mut spec = CommandSpec::parse(&self.command)
+            .map_err(|e| invalid(e.to_string()))?
+            .args(&self.args)
+            .envs(&self.env);
+        if let Some(cwd) = &self.cwd {
+            spec = spec.cwd(cwd);
+        }
+        if let Some(timeout) = &self.timeout {
+            spec = spec.timeout(timeout.to_duration().map_err(invalid)?);
+        }
+        Ok(spec)
+    }
+
+    /// Where the log of the task is stored: `log_dir` when it is set, the default log folder otherwise.
+    pub fn filestore(&self) -> Filestore {
+        self.log_dir
+            .as_ref()
+            .map_or_else(Filestore::default, Filestore::new)
+    }
+}
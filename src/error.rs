@@ -0,0 +1,190 @@
+//! # Error module
+//! The errors that can happen while executing a command or storing its output,
+//! so callers can tell "binary not found" from "non-zero exit" without string matching.
+
+use crate::output::ExecOutput;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+/// Everything that can go wrong in `Execute`, `CommandSpec` and `Filestore`.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::execute::{CommandError, Execute};
+///
+/// match Execute::run("this-binary-does-not-exist", &[]) {
+///     Err(CommandError::NotFound { program }) => assert_eq!(program, "this-binary-does-not-exist"),
+///     other => panic!("unexpected {:?}", other),
+/// }
+/// match Execute::run("sh", &["-c", "echo oops >&2; exit 4"]) {
+///     Err(CommandError::NonZeroExit { code, .. }) => assert_eq!(code, 4),
+///     other => panic!("unexpected {:?}", other),
+/// }
+/// ```
+#[derive(Debug)]
+pub enum CommandError {
+    /// the program couldn't be found in `PATH`
+    NotFound { program: String },
+    /// the program exists but we aren't allowed to execute it
+    PermissionDenied { program: String },
+    /// spawning the program failed for another reason
+    SpawnFailed { program: String, source: io::Error },
+    /// the command ran but exited with a non-zero code
+    NonZeroExit {
+        code: i32,
+        stderr: String,
+        output: Box<ExecOutput>,
+    },
+    /// the command was killed by a signal
+    Signaled {
+        signal: i32,
+        output: Box<ExecOutput>,
+    },
+    /// the command didn't finish in time and was killed
+    Timeout {
+        after: Duration,
+        output: Box<ExecOutput>,
+    },
+    /// the command was stopped through its `CancelHandle`
+    Cancelled { output: Box<ExecOutput> },
+    /// the command was never started because another one failed first
+    Skipped { program: String },
+    /// a command line couldn't be split into words
+    InvalidCommandLine { line: String, reason: String },
+    /// a pattern given to `Interactive::expect` isn't a valid regular expression
+    InvalidPattern { pattern: String, reason: String },
+    /// an interactive command didn't print what was expected in time
+    ExpectTimeout {
+        pattern: String,
+        after: Duration,
+        seen: String,
+    },
+    /// an interactive command exited without printing what was expected
+    ExpectEof { pattern: String, seen: String },
+    /// any other I/O failure (waiting for the command, writing a log file...)
+    Io(io::Error),
+}
+
+impl CommandError {
+    /// Classify an error returned while spawning `program`.
+    pub fn spawn(program: &str, source: io::Error) -> Self {
+        let program = program.to_string();
+        match source.kind() {
+            io::ErrorKind::NotFound => CommandError::NotFound { program },
+            io::ErrorKind::PermissionDenied => CommandError::PermissionDenied { program },
+            _ => CommandError::SpawnFailed { program, source },
+        }
+    }
+
+    /// Turn a finished command into an error when it didn't succeed.
+    pub fn check(output: ExecOutput) -> Result<ExecOutput, Self> {
+        if output.success() {
+            return Ok(output);
+        }
+        match (output.code(), output.signal) {
+            (Some(code), _) => Err(CommandError::NonZeroExit {
+                code,
+                stderr: output.stderr_str().into_owned(),
+                output: Box::new(output),
+            }),
+            (None, signal) => Err(CommandError::Signaled {
+                signal: signal.unwrap_or_default(),
+                output: Box::new(output),
+            }),
+        }
+    }
+
+    /// The output the command produced before failing, when it ran at all.
+    pub fn output(&self) -> Option<&ExecOutput> {
+        match self {
+            CommandError::NonZeroExit { output, .. }
+            | CommandError::Signaled { output, .. }
+            | CommandError::Timeout { output, .. }
+            | CommandError::Cancelled { output } => Some(output),
+            _ => None,
+        }
+    }
+
+    /// The exit code of the command, when it exited on its own with a non-zero code.
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            CommandError::NonZeroExit { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::NotFound { program } => write!(f, "command '{}' not found", program),
+            CommandError::PermissionDenied { program } => {
+                write!(f, "permission denied to execute '{}'", program)
+            }
+            CommandError::SpawnFailed { program, source } => {
+                write!(f, "failed to execute cmd '{}': {}", program, source)
+            }
+            CommandError::NonZeroExit { code, stderr, .. } => {
+                write!(f, "Command failed with exit code {}: {}", code, stderr)
+            }
+            CommandError::Signaled { signal, output } => {
+                write!(
+                    f,
+                    "Command '{}' killed by signal {}",
+                    output.command, signal
+                )
+            }
+            CommandError::Timeout { after, output } => {
+                write!(
+                    f,
+                    "Command '{}' timed out after {:?}",
+                    output.command, after
+                )
+            }
+            CommandError::Cancelled { output } => {
+                write!(f, "Command '{}' was cancelled", output.command)
+            }
+            CommandError::Skipped { program } => {
+                write!(f, "Command '{}' skipped after an earlier failure", program)
+            }
+            CommandError::InvalidCommandLine { line, reason } => {
+                write!(f, "invalid command line '{}': {}", line, reason)
+            }
+            CommandError::InvalidPattern { pattern, reason } => {
+                write!(f, "invalid pattern '{}': {}", pattern, reason)
+            }
+            CommandError::ExpectTimeout {
+                pattern,
+                after,
+                seen,
+            } => write!(
+                f,
+                "'{}' not seen after {:?}, got: {:?}",
+                pattern, after, seen
+            ),
+            CommandError::ExpectEof { pattern, seen } => write!(
+                f,
+                "Command exited before printing '{}', got: {:?}",
+                pattern, seen
+            ),
+            CommandError::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CommandError::SpawnFailed { source, .. } => Some(source),
+            CommandError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CommandError {
+    fn from(err: io::Error) -> Self {
+        CommandError::Io(err)
+    }
+}
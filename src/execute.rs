@@ -5,22 +5,816 @@
 //! using our crate will help you with the process of creating automated programs
 //! instead of using other languages.
 
+use crate::batch::{Batch, BatchOutput};
+use crate::color::Col;
+use crate::filestore::{Filestore, DEFAULT_FILENAME};
+use crate::interactive::Interactive;
+use crate::process::{Feed, Launch, Limits, Merged, OutputEvents, Running};
+use crate::pty::{self, PtySize};
+use crate::retry::RetryPolicy;
+use crate::shell::Shell;
+use crate::shellwords;
 use std::fs::File;
-use std::io::Write;
-use std::process::{Command, Stdio};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub use crate::error::CommandError;
+pub use crate::output::ExecOutput;
+pub use crate::pipeline::{Pipeline, PipelineOutput};
+pub use crate::process::{CancelHandle, OutputEvent};
 
 pub struct Execute;
-/// this const is intended to hold the name of the file where we will store our output
-const FILE_NAME: &str = "ExecuteLog.log";
+/// how long a timed out or cancelled command gets between SIGTERM and SIGKILL by default
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Turn the dry-run mode on or off for the whole program.
+///
+/// In dry-run mode commands aren't spawned: their quoted command line, working
+/// directory and environment changes are printed instead and they report a success
+/// with an empty output. `Filestore` prints the folders and files it would write
+/// without touching them. `CommandSpec::dry_run` overrides it for one command.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::execute::{self, Execute};
+///
+/// execute::set_dry_run(true);
+/// let out = Execute::run("rm", &["-rf", "build output"]).unwrap();
+/// assert_eq!(out.command, "rm -rf 'build output'");
+/// assert!(out.stdout.is_empty());
+/// execute::set_dry_run(false);
+/// ```
+pub fn set_dry_run(enabled: bool) {
+    DRY_RUN.store(enabled, Ordering::Relaxed);
+}
+
+/// `true` when the dry-run mode was turned on with `set_dry_run`.
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// print a line of the dry-run report
+pub(crate) fn print_dry_run(line: &str) {
+    println!("{} {}", Col::Yellow.print_col("[dry-run]"), line);
+}
+
+/// How one of the output streams (stdout/stderr) of a command is wired up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    /// share the stream of the current process (the output shows up in the console)
+    Inherit,
+    /// capture the stream so it ends up in the returned output
+    Piped,
+    /// discard the stream (`/dev/null`)
+    Null,
+}
+
+impl Stream {
+    fn to_stdio(self) -> Stdio {
+        match self {
+            Stream::Inherit => Stdio::inherit(),
+            Stream::Piped => Stdio::piped(),
+            Stream::Null => Stdio::null(),
+        }
+    }
+}
+
+/// Where the standard input of a command comes from.
+#[derive(Debug, Clone)]
+pub enum Input {
+    /// share the stdin of the current process
+    Inherit,
+    /// an empty stdin (`/dev/null`)
+    Null,
+    /// feed these bytes to the command, then close its stdin
+    Bytes(Vec<u8>),
+    /// read stdin from the given file
+    File(PathBuf),
+    /// copy everything the reader yields to the command (see `Input::reader`)
+    Reader(SharedReader),
+    /// run this command first (checked like `run`) and feed its stdout to the command
+    Command(Box<CommandSpec>),
+}
+
+impl Input {
+    /// Feed the command with what `reader` yields, on a thread of its own.
+    ///
+    /// The reader is consumed by the first execution: running the same spec
+    /// (or a clone of it) again gets an empty stdin.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::{CommandSpec, Input};
+    /// use std::io::Cursor;
+    ///
+    /// let out = CommandSpec::new("wc")
+    ///     .arg("-l")
+    ///     .stdin(Input::reader(Cursor::new("a\nb\nc\n")))
+    ///     .run()
+    ///     .unwrap();
+    /// assert_eq!(out.stdout_str().trim(), "3");
+    /// ```
+    pub fn reader(reader: impl Read + Send + 'static) -> Self {
+        Input::Reader(SharedReader(Arc::new(Mutex::new(Some(Box::new(reader))))))
+    }
+
+    /// Feed the command with the stdout of `spec`, which runs to completion first.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::{CommandSpec, Input};
+    ///
+    /// let out = CommandSpec::new("sort")
+    ///     .stdin(Input::command(CommandSpec::new("printf").arg("b\\na\\n")))
+    ///     .run()
+    ///     .unwrap();
+    /// assert_eq!(out.stdout_str(), "a\nb\n");
+    /// ```
+    pub fn command(spec: CommandSpec) -> Self {
+        Input::Command(Box::new(spec))
+    }
+}
+
+/// The stdout of a finished command, to feed it to another one.
+impl From<&ExecOutput> for Input {
+    fn from(output: &ExecOutput) -> Self {
+        Input::Bytes(output.stdout.clone())
+    }
+}
+
+/// A reader handed over to `Input::Reader`, shared by the clones of a spec
+/// and taken by the first command that runs.
+#[derive(Clone)]
+pub struct SharedReader(Arc<Mutex<Option<Box<dyn Read + Send>>>>);
+
+impl SharedReader {
+    fn take(&self) -> Option<Box<dyn Read + Send>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+}
+
+impl std::fmt::Debug for SharedReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SharedReader")
+    }
+}
+
+/// # CommandSpec
+/// A full description of a command to execute: program, arguments, working
+/// directory, environment, stdin source and how each output stream is handled.
+///
+/// `Execute::run` and `Execute::exe` are thin wrappers over this type, use it
+/// directly whenever you need more than a program name and its arguments.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::execute::CommandSpec;
+///
+/// let out = CommandSpec::new("sh")
+///     .args(["-c", "echo $GREETING from $(pwd)"])
+///     .cwd("/")
+///     .env("GREETING", "hello")
+///     .run()
+///     .unwrap();
+/// assert_eq!(out.stdout_str(), "hello from /\n");
+/// ```
+#[derive(Debug, Clone)]
+pub struct CommandSpec {
+    program: String,
+    args: Vec<String>,
+    cwd: Option<PathBuf>,
+    env: Vec<(String, Option<String>)>,
+    env_clear: bool,
+    stdin: Input,
+    stdout: Stream,
+    stderr: Stream,
+    merge_stderr: bool,
+    pty: Option<PtySize>,
+    limits: Limits,
+    retry: Option<RetryPolicy>,
+    dry_run: Option<bool>,
+}
+
+impl CommandSpec {
+    /// Create a spec for `program` with no arguments.
+    ///
+    /// By default stdin is inherited and stdout and stderr are captured,
+    /// the same way `Execute::run` behaves.
+    pub fn new(program: impl Into<String>) -> Self {
+        CommandSpec {
+            program: program.into(),
+            args: Vec::new(),
+            cwd: None,
+            env: Vec::new(),
+            env_clear: false,
+            stdin: Input::Inherit,
+            stdout: Stream::Piped,
+            stderr: Stream::Piped,
+            merge_stderr: false,
+            pty: None,
+            limits: Limits {
+                grace: DEFAULT_GRACE_PERIOD,
+                ..Limits::default()
+            },
+            retry: None,
+            dry_run: None,
+        }
+    }
+
+    /// Create a spec from a whole command line, split like a POSIX shell would
+    /// (see `shellwords::split`), the first word being the program.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::CommandSpec;
+    ///
+    /// let spec = CommandSpec::parse("git log --format='%h %s'").unwrap();
+    /// assert_eq!(spec.get_program(), "git");
+    /// assert_eq!(spec.get_args(), ["log", "--format=%h %s"]);
+    /// assert!(CommandSpec::parse("echo 'oops").is_err());
+    /// ```
+    pub fn parse(line: &str) -> Result<Self, CommandError> {
+        let invalid = |reason: String| CommandError::InvalidCommandLine {
+            line: line.to_string(),
+            reason,
+        };
+        let mut words = shellwords::split(line).map_err(|e| invalid(e.to_string()))?;
+        if words.is_empty() {
+            return Err(invalid("the line is empty".to_string()));
+        }
+        let program = words.remove(0);
+        Ok(CommandSpec::new(program).args(words))
+    }
+
+    /// Append a single argument.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Append several arguments.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Run the command inside `dir` instead of the current directory.
+    pub fn cwd(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(dir.into());
+        self
+    }
+
+    /// Set an environment variable for the command.
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((key.into(), Some(value.into())));
+        self
+    }
+
+    /// Set several environment variables for the command.
+    pub fn envs<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.env
+            .extend(vars.into_iter().map(|(k, v)| (k.into(), Some(v.into()))));
+        self
+    }
+
+    /// Remove an environment variable from the command's environment.
+    pub fn env_remove(mut self, key: impl Into<String>) -> Self {
+        self.env.push((key.into(), None));
+        self
+    }
+
+    /// Start from an empty environment instead of inheriting the current one.
+    /// Variables set with `env` after or before this call are still passed.
+    pub fn env_clear(mut self) -> Self {
+        self.env_clear = true;
+        self
+    }
+
+    /// Choose where stdin comes from.
+    pub fn stdin(mut self, input: Input) -> Self {
+        self.stdin = input;
+        self
+    }
+
+    /// Choose what happens with stdout.
+    pub fn stdout(mut self, stream: Stream) -> Self {
+        self.stdout = stream;
+        self
+    }
+
+    /// Choose what happens with stderr.
+    pub fn stderr(mut self, stream: Stream) -> Self {
+        self.stderr = stream;
+        self
+    }
+
+    /// Send stderr into the same pipe as stdout, like `2>&1` in a shell.
+    ///
+    /// Both streams end up interleaved in chronological order in `ExecOutput::stdout`
+    /// (and in `OutputEvent::Stdout` when streaming), `ExecOutput::stderr` stays empty.
+    /// The stream settings are ignored while this is on.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::CommandSpec;
+    ///
+    /// let out = CommandSpec::new("sh")
+    ///     .args(["-c", "echo one; echo two >&2; echo three"])
+    ///     .merge_stderr()
+    ///     .run()
+    ///     .unwrap();
+    /// assert_eq!(out.stdout_str(), "one\ntwo\nthree\n");
+    /// assert!(out.stderr.is_empty());
+    /// ```
+    pub fn merge_stderr(mut self) -> Self {
+        self.merge_stderr = true;
+        self
+    }
+
+    /// Run the command inside a pseudo-terminal of `size` instead of pipes, so it
+    /// keeps the colors and progress output it only prints to a terminal.
+    ///
+    /// Everything the terminal shows, stdout and stderr interleaved, ends up in
+    /// `ExecOutput::stdout` with the `\r\n` line endings of a terminal; `ExecOutput::stderr`
+    /// stays empty. The stream settings and `merge_stderr` are ignored while this is on,
+    /// stdin is still set with `stdin`. Only available on unix, elsewhere the command
+    /// fails to start with `ErrorKind::Unsupported`.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::CommandSpec;
+    /// use commandcrafter::pty::PtySize;
+    ///
+    /// let out = CommandSpec::new("sh")
+    ///     .args(["-c", "[ -t 1 ] && echo terminal; stty size < /dev/tty"])
+    ///     .pty(PtySize::new(120, 40))
+    ///     .run()
+    ///     .unwrap();
+    /// assert_eq!(out.stdout_str(), "terminal\r\n40 120\r\n");
+    /// ```
+    pub fn pty(mut self, size: PtySize) -> Self {
+        self.pty = Some(size);
+        self
+    }
+
+    /// Stop the command once it has been running for `timeout`.
+    ///
+    /// The command and everything it started get SIGTERM, then SIGKILL if they
+    /// are still around after the grace period. The run then fails with
+    /// `CommandError::Timeout`, which holds the output collected so far.
+    ///
+    /// On unix a watched command runs in its own process group, so it no longer
+    /// receives the Ctrl-C of the terminal and can't read from it.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::{CommandError, CommandSpec};
+    /// use std::time::Duration;
+    ///
+    /// let res = CommandSpec::new("sh")
+    ///     .args(["-c", "echo started; sleep 10"])
+    ///     .timeout(Duration::from_millis(200))
+    ///     .run();
+    /// match res {
+    ///     Err(CommandError::Timeout { output, .. }) => assert_eq!(output.stdout_str(), "started\n"),
+    ///     other => panic!("unexpected {:?}", other),
+    /// }
+    /// ```
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.limits.timeout = Some(timeout);
+        self
+    }
+
+    /// How long a timed out or cancelled command may take to exit after SIGTERM
+    /// before it is killed with SIGKILL (2 seconds by default).
+    pub fn grace_period(mut self, grace: Duration) -> Self {
+        self.limits.grace = grace;
+        self
+    }
+
+    /// Stop the command when `handle` is cancelled, the run then fails with `CommandError::Cancelled`.
+    ///
+    /// Can be called more than once, the command stops as soon as any of the handles is cancelled.
+    pub fn cancel_on(mut self, handle: &CancelHandle) -> Self {
+        self.limits.cancel.push(handle.clone());
+        self
+    }
+
+    /// Run the command again when it fails, following `policy`.
+    ///
+    /// Applies to `run` and `exe_checked` (and so to batches and task graphs),
+    /// `output`, `exe`, `events` and `stream` always run the command once.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Only print what would be executed instead of executing it (see `set_dry_run`),
+    /// `false` runs this command even when the dry-run mode is on globally.
+    pub fn dry_run(mut self, enabled: bool) -> Self {
+        self.dry_run = Some(enabled);
+        self
+    }
+
+    /// The program that will be executed.
+    pub fn get_program(&self) -> &str {
+        &self.program
+    }
+
+    /// The arguments passed to the program.
+    pub fn get_args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// The working directory, if one was set.
+    pub fn get_cwd(&self) -> Option<&Path> {
+        self.cwd.as_deref()
+    }
+
+    /// The environment changes in the order they were made,
+    /// `None` means the variable is removed.
+    pub fn get_envs(&self) -> &[(String, Option<String>)] {
+        &self.env
+    }
+
+    /// Where stdin comes from.
+    pub fn get_stdin(&self) -> &Input {
+        &self.stdin
+    }
+
+    /// The retry policy, if one was set.
+    pub fn get_retry(&self) -> Option<&RetryPolicy> {
+        self.retry.as_ref()
+    }
+
+    /// the handles set with `cancel_on`
+    pub(crate) fn get_cancel_handles(&self) -> &[CancelHandle] {
+        &self.limits.cancel
+    }
+
+    /// Build the `std::process::Command` described by this spec.
+    ///
+    /// Stdin is left to the caller because some sources (bytes) need the
+    /// child to be spawned before they can be fed.
+    fn to_command(&self) -> Command {
+        let mut cmd = Command::new(&self.program);
+        cmd.args(&self.args);
+        if let Some(dir) = &self.cwd {
+            cmd.current_dir(dir);
+        }
+        if self.env_clear {
+            cmd.env_clear();
+        }
+        for (key, value) in &self.env {
+            match value {
+                Some(value) => cmd.env(key, value),
+                None => cmd.env_remove(key),
+            };
+        }
+        cmd.stdout(self.stdout.to_stdio());
+        cmd.stderr(self.stderr.to_stdio());
+        cmd
+    }
+
+    /// The command line as it would be typed in a shell, used in messages and logs.
+    /// Every word is quoted when needed, so the line can be pasted back into a shell.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::CommandSpec;
+    ///
+    /// let spec = CommandSpec::new("grep").args(["-r", "fn main", "src/"]);
+    /// assert_eq!(spec.command_line(), "grep -r 'fn main' src/");
+    /// ```
+    pub fn command_line(&self) -> String {
+        shellwords::join(std::iter::once(&self.program).chain(&self.args))
+    }
+
+    /// `true` when this command is only printed, not executed.
+    pub fn get_dry_run(&self) -> bool {
+        self.dry_run.unwrap_or_else(is_dry_run)
+    }
+
+    /// What a dry run prints: the quoted command line, then the working directory
+    /// and the changes made to the environment of the current process, when there are some.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::CommandSpec;
+    ///
+    /// let spec = CommandSpec::new("make")
+    ///     .arg("install")
+    ///     .cwd("/tmp")
+    ///     .env("DESTDIR", "/opt/my app")
+    ///     .env_remove("COMMANDCRAFTER_UNSET_IN_DOCS");
+    /// assert_eq!(
+    ///     spec.preview(),
+    ///     ["$ make install", "  cwd: /tmp", "  env: DESTDIR='/opt/my app'"]
+    /// );
+    /// ```
+    pub fn preview(&self) -> Vec<String> {
+        let mut lines = vec![format!("$ {}", self.command_line())];
+        if let Some(dir) = &self.cwd {
+            lines.push(format!(
+                "  cwd: {}",
+                shellwords::quote(&dir.to_string_lossy())
+            ));
+        }
+        let mut changes = Vec::new();
+        if self.env_clear {
+            changes.push("(cleared)".to_string());
+        }
+        for (key, value) in &self.env {
+            let current = std::env::var_os(key);
+            match value {
+                Some(value) if self.env_clear || current.as_deref() != Some(value.as_ref()) => {
+                    changes.push(format!("{}={}", key, shellwords::quote(value)))
+                }
+                None if !self.env_clear && current.is_some() => changes.push(format!("-{}", key)),
+                _ => {}
+            }
+        }
+        if !changes.is_empty() {
+            lines.push(format!("  env: {}", changes.join(" ")));
+        }
+        if let Some(size) = self.pty {
+            lines.push(format!("  pty: {}x{}", size.cols, size.rows));
+        }
+        lines
+    }
+
+    /// print the preview and build the output of a command that would have succeeded
+    pub(crate) fn dry_output(&self) -> ExecOutput {
+        for line in self.preview() {
+            print_dry_run(&line);
+        }
+        ExecOutput::dry_run(self.command_line())
+    }
+
+    /// Prepare the `std::process::Command` with stdin wired up and everything needed to spawn it.
+    pub(crate) fn launch(&self) -> Result<Launch, CommandError> {
+        let mut cmd = self.to_command();
+        // one pipe shared by both streams, the reading end is drained as stdout
+        let merged = if let Some(size) = self.pty {
+            Some(Merged::Terminal(pty::attach(&mut cmd, size)?))
+        } else if self.merge_stderr {
+            let (reader, writer) = std::io::pipe()?;
+            cmd.stdout(writer.try_clone()?);
+            cmd.stderr(writer);
+            Some(Merged::Pipe(reader))
+        } else {
+            None
+        };
+        let input = match &self.stdin {
+            Input::Inherit => {
+                cmd.stdin(Stdio::inherit());
+                None
+            }
+            Input::Null => {
+                cmd.stdin(Stdio::null());
+                None
+            }
+            Input::Bytes(bytes) => {
+                cmd.stdin(Stdio::piped());
+                Some(Feed::Bytes(bytes.clone()))
+            }
+            Input::File(path) => {
+                cmd.stdin(File::open(path)?);
+                None
+            }
+            Input::Reader(reader) => {
+                cmd.stdin(Stdio::piped());
+                Some(reader.take().map_or(Feed::Bytes(Vec::new()), Feed::Reader))
+            }
+            Input::Command(spec) => {
+                cmd.stdin(Stdio::piped());
+                Some(Feed::Bytes(spec.run()?.stdout))
+            }
+        };
+        Ok(Launch {
+            cmd,
+            program: self.program.clone(),
+            command: self.command_line(),
+            input,
+            merged,
+            limits: self.limits.clone(),
+        })
+    }
+
+    /// this spec with an inherited stdout or stderr captured instead
+    pub(crate) fn captured(&self) -> CommandSpec {
+        let mut spec = self.clone();
+        for stream in [&mut spec.stdout, &mut spec.stderr] {
+            if *stream == Stream::Inherit {
+                *stream = Stream::Piped;
+            }
+        }
+        spec
+    }
+
+    /// Spawn the command with stdin wired up.
+    fn spawn(&self) -> Result<Running, CommandError> {
+        Running::spawn(self.launch()?)
+    }
+
+    /// Execute the command and return everything about it, whatever its exit status.
+    ///
+    /// Only fails if the command can't be spawned or waited for, a non-zero
+    /// exit is reported through `ExecOutput::success`.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::CommandSpec;
+    ///
+    /// let out = CommandSpec::new("sh").args(["-c", "exit 3"]).output().unwrap();
+    /// assert!(!out.success());
+    /// assert_eq!(out.code(), Some(3));
+    /// ```
+    pub fn output(&self) -> Result<ExecOutput, CommandError> {
+        if self.get_dry_run() {
+            return Ok(self.dry_output());
+        }
+        self.spawn()?.wait()
+    }
+
+    /// Execute the command and return its output.
+    ///
+    /// Fails if the command can't be spawned, exits with a non-zero status
+    /// (`CommandError::NonZeroExit`) or is killed by a signal (`CommandError::Signaled`).
+    /// With a `retry` policy the failures it allows are tried again first.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::{CommandSpec, Input};
+    ///
+    /// let out = CommandSpec::new("tr")
+    ///     .args(["a-z", "A-Z"])
+    ///     .stdin(Input::Bytes(b"shout".to_vec()))
+    ///     .run()
+    ///     .unwrap();
+    /// assert_eq!(out.stdout, b"SHOUT");
+    /// ```
+    pub fn run(&self) -> Result<ExecOutput, CommandError> {
+        match &self.retry {
+            Some(policy) => policy.run_until(self.get_cancel_handles(), || self.output()),
+            None => CommandError::check(self.output()?),
+        }
+    }
+
+    /// Execute the command and iterate over its output line by line while it runs.
+    ///
+    /// Stdout and stderr are read concurrently, an inherited stream is captured
+    /// instead so its lines show up as events. The last event is `OutputEvent::Exit`,
+    /// call `OutputEvents::finish` afterwards to get the accumulated `ExecOutput`.
+    /// When reading the output fails before the command exited, the events end
+    /// without it and `finish` returns the error.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::{CommandSpec, OutputEvent};
+    ///
+    /// let mut events = CommandSpec::new("sh")
+    ///     .args(["-c", "echo building; echo warning >&2; echo done"])
+    ///     .events()
+    ///     .unwrap();
+    /// for event in events.by_ref() {
+    ///     match event {
+    ///         OutputEvent::Stdout(line) => println!("{}", line),
+    ///         OutputEvent::Stderr(line) => eprintln!("{}", line),
+    ///         OutputEvent::Exit(status) => assert!(status.success()),
+    ///     }
+    /// }
+    /// let out = events.finish().unwrap();
+    /// assert_eq!(out.stdout_str(), "building\ndone\n");
+    /// assert_eq!(out.stderr_str(), "warning\n");
+    /// ```
+    pub fn events(&self) -> Result<OutputEvents, CommandError> {
+        if self.get_dry_run() {
+            return Ok(OutputEvents::done(self.dry_output()));
+        }
+        Ok(OutputEvents::new(self.captured().spawn()?))
+    }
+
+    /// Execute the command and call `on_event` for every line it prints, as it prints it.
+    ///
+    /// Returns the accumulated output once the command exited, checked like `run`.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::{CommandSpec, OutputEvent};
+    ///
+    /// let mut log = Vec::new();
+    /// let out = CommandSpec::new("sh")
+    ///     .args(["-c", "echo one; echo two"])
+    ///     .stream(|event| {
+    ///         // tee: show the line right away and keep it for later
+    ///         if let OutputEvent::Stdout(line) = event {
+    ///             println!("{}", line);
+    ///             log.push(line.clone());
+    ///         }
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(log, ["one", "two"]);
+    /// assert_eq!(out.stdout_str(), "one\ntwo\n");
+    /// ```
+    pub fn stream<F>(&self, mut on_event: F) -> Result<ExecOutput, CommandError>
+    where
+        F: FnMut(&OutputEvent),
+    {
+        let mut events = self.events()?;
+        for event in events.by_ref() {
+            on_event(&event);
+        }
+        CommandError::check(events.finish()?)
+    }
+
+    /// Start the command with its stdin open, to write to it a piece at a time
+    /// while reading what it prints (see `Interactive`).
+    ///
+    /// The stdin set on the spec is ignored, an inherited stdout or stderr is captured.
+    /// A timeout or cancel handle still applies.
+    pub fn interactive(&self) -> Result<Interactive, CommandError> {
+        if self.get_dry_run() {
+            return Ok(Interactive::dry_run(self.dry_output()));
+        }
+        let mut launch = self.captured().stdin(Input::Null).launch()?;
+        launch.cmd.stdin(Stdio::piped());
+        let mut running = Running::spawn(launch)?;
+        let stdin = running
+            .take_stdin()
+            .map(|stdin| Box::new(stdin) as Box<dyn Write + Send>);
+        Ok(Interactive::new(self.command_line(), stdin, running))
+    }
+
+    /// Execute the command with stdout inherited so colors and formatting
+    /// are preserved in the console, and return its exit status.
+    ///
+    /// Stderr follows the spec: piped (the default) it is captured and only reported
+    /// by `exe_checked` when the command fails, inherited it shows up in the console.
+    /// A non-zero exit is not an error here, check the returned status or use `exe_checked`.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::CommandSpec;
+    ///
+    /// let status = CommandSpec::new("ls").arg("--color=always").cwd("/").exe().unwrap();
+    /// assert!(status.success());
+    /// let status = CommandSpec::new("sh").args(["-c", "exit 2"]).exe().unwrap();
+    /// assert_eq!(status.code(), Some(2));
+    /// ```
+    pub fn exe(&self) -> Result<ExitStatus, CommandError> {
+        Ok(self.clone().stdout(Stream::Inherit).output()?.status)
+    }
+
+    /// Like `exe`, but fails like `set -e` would when the command doesn't succeed.
+    ///
+    /// The error is `CommandError::NonZeroExit` (or `Signaled`), holding stderr when it was piped.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::{CommandError, CommandSpec, Stream};
+    ///
+    /// let res = CommandSpec::new("sh")
+    ///     .args(["-c", "echo 'no such package' >&2; exit 1"])
+    ///     .stderr(Stream::Piped)
+    ///     .exe_checked();
+    /// match res {
+    ///     Err(CommandError::NonZeroExit { code, stderr, .. }) => {
+    ///         assert_eq!(code, 1);
+    ///         assert_eq!(stderr, "no such package\n");
+    ///     }
+    ///     other => panic!("unexpected {:?}", other),
+    /// }
+    /// ```
+    pub fn exe_checked(&self) -> Result<ExitStatus, CommandError> {
+        Ok(self.clone().stdout(Stream::Inherit).run()?.status)
+    }
+}
+
 /// Implement the **Execute struct**
 impl Execute {
     /// Executes a shell command with the specified arguments.
     ///
     /// This function takes a command as a string and a slice of arguments, spawns the command,
     /// and inherits the standard output (stdout) to preserve any colors or formatting in the output.
+    /// Stderr is inherited too, so error messages show up in the console.
     ///
-    /// If the command fails to execute, an error message will be printed to the standard error (stderr)
-    /// and the process will exit with a non-zero status code.
+    /// It is a shortcut for `CommandSpec::new(command).args(arguments).exe()`.
     ///
     /// # Arguments
     ///
@@ -32,57 +826,50 @@ impl Execute {
     /// ```
     /// use commandcrafter::execute::Execute;
     ///
-    /// Execute::exe("echo", &["hello", "world"]).unwrap();
+    /// let status = Execute::exe("echo", &["hello", "world"]).unwrap();
+    /// assert!(status.success());
     /// ```
     ///
     /// # Errors
     ///
-    /// This function will return a `Result<(), std::io::Error>` that contains an error if the command fails to execute.
-    pub fn exe(command: &str, arguments: &[&str]) -> Result<(), std::io::Error> {
-        let output = Command::new(command)
-            .args(arguments)
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|e| {
-                std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("failed to execute cmd '{}': {}", command, e),
-                )
-            })?;
-        let _ = output.wait_with_output().map_err(|err| {
-            std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Failed to wait for command '{}': {}", command, err),
-            )
-        });
-
-        Ok(())
-        // let _ = output.wait_with_output().map_err(|err| {
-        //     std::io::Error::new(
-        //         std::io::ErrorKind::Other,
-        //         format!("Failed to wait for command '{}': {}", command, err),
-        //     )
-        // });
-        // let output = Command::new(command)
-        //     .args(arguments)
-        //     .stdout(Stdio::inherit())
-        //     .spawn();
-        // match rs {
-        //     Ok(_) => {}
-        //     Err(e) => {
-        //         eprintln!("Command \'{}\' can't be executed: {}", command, e);
-        //         std::process::exit(1)
-        //     }
-        // }
+    /// This function will return a `CommandError` if the command can't be executed,
+    /// a non-zero exit is only reported through the returned `ExitStatus`.
+    pub fn exe(command: &str, arguments: &[&str]) -> Result<ExitStatus, CommandError> {
+        CommandSpec::new(command)
+            .args(arguments.iter().copied())
+            .stderr(Stream::Inherit)
+            .exe()
+    }
+    /// Executes a shell command like `Execute::exe`, but fails like `set -e` would.
+    /// Stderr is captured rather than shown, so the error holds the message of the command.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use commandcrafter::execute::Execute;
+    ///
+    /// assert!(Execute::exe_checked("true", &[]).is_ok());
+    /// assert!(Execute::exe_checked("false", &[]).is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This function will return a `CommandError` if the command can't be executed
+    /// or doesn't exit successfully.
+    pub fn exe_checked(command: &str, arguments: &[&str]) -> Result<ExitStatus, CommandError> {
+        CommandSpec::new(command)
+            .args(arguments.iter().copied())
+            .exe_checked()
     }
     /// # Execute::run;
-    /// this method is used to execute the command and return the output
+    /// this method is used to execute the command and return the output,
+    /// it is a shortcut for `CommandSpec::new(command).args(arguments).run()`
     /// ## Arguments
     /// * `command` - the name of the command
     /// * `arguments` - the arguments of the command to be executed
     /// ## Returns
-    /// the output of the command as a vector of bytes
+    /// the output of the command as an `ExecOutput` (stdout, stderr, exit status, duration...),
+    /// stderr is captured so a failing command has its message in the `Err` value
     /// ###  Example
     /// ```rust
     /// use commandcrafter::execute::Execute;
@@ -94,35 +881,117 @@ impl Execute {
     ///     std::process::exit(1)
     /// }
     /// ```
-    pub fn run(command: &str, arguments: &[&str]) -> Result<Vec<u8>, String> {
-        // execute the command and return the output
-        let output = Command::new(command)
-            .args(arguments)
-            .stdout(Stdio::piped())
-            .spawn()
-            .map_err(|e| format!("failed to execute cmd '{}' : {}", command, e))?;
-
-        let result = output
-            .wait_with_output()
-            .map_err(|err| format!("Failed to wait for command '{}': {}", command, err))?;
-
-        if result.status.success() {
-            Ok(result.stdout)
-        } else {
-            Err(format!(
-                "Command failed with exit code {}: {}",
-                result.status.code().unwrap_or_default(),
-                String::from_utf8_lossy(&result.stderr)
-            ))
-        }
+    pub fn run(command: &str, arguments: &[&str]) -> Result<ExecOutput, CommandError> {
+        CommandSpec::new(command)
+            .args(arguments.iter().copied())
+            .run()
+    }
+    /// # Execute::run_with_retry;
+    /// this method works like `Execute::run` but runs the command again when it fails
+    /// the way `policy` allows it, useful for commands touching the network.
+    /// The failed attempts are kept in `ExecOutput::attempts`.
+    /// ###  Example
+    /// ```rust
+    /// use commandcrafter::execute::Execute;
+    /// use commandcrafter::retry::{Backoff, RetryPolicy};
+    /// use std::time::Duration;
+    ///
+    /// let policy = RetryPolicy::new(3).backoff(Backoff::fixed(Duration::from_millis(10)));
+    /// let err = Execute::run_with_retry("sh", &["-c", "exit 5"], &policy).unwrap_err();
+    /// assert_eq!(err.output().unwrap().attempts.len(), 2);
+    /// ```
+    pub fn run_with_retry(
+        command: &str,
+        arguments: &[&str],
+        policy: &RetryPolicy,
+    ) -> Result<ExecOutput, CommandError> {
+        CommandSpec::new(command)
+            .args(arguments.iter().copied())
+            .retry(policy.clone())
+            .run()
+    }
+    /// # Execute::run_str;
+    /// this method executes a command given as a single line, like one read from
+    /// a config file. The line is split into words the way a POSIX shell would,
+    /// honouring quotes and escapes, but nothing is expanded (no globs, no variables).
+    /// ###  Example
+    /// ```rust
+    /// use commandcrafter::execute::Execute;
+    /// let out = Execute::run_str("printf '%s-%s' 'a b' c").unwrap();
+    /// assert_eq!(out.stdout_str(), "a b-c");
+    /// ```
+    pub fn run_str(line: &str) -> Result<ExecOutput, CommandError> {
+        CommandSpec::parse(line)?.run()
+    }
+    /// # Execute::shell;
+    /// this method runs `script` through `sh -c`, for when shell features (globs,
+    /// redirects, `&&`...) are really needed. The arguments are not pasted into
+    /// the script, they are passed as the positional parameters `$1`, `$2`...
+    /// Use `shell::Shell` to pick another shell or turn on strict mode.
+    /// ###  Example
+    /// ```rust
+    /// use commandcrafter::execute::Execute;
+    /// let out = Execute::shell("echo \"hello $1\" | tr a-z A-Z", &["you & me"]).unwrap();
+    /// assert_eq!(out.stdout_str(), "HELLO YOU & ME\n");
+    /// ```
+    pub fn shell(script: &str, arguments: &[&str]) -> Result<ExecOutput, CommandError> {
+        Shell::default()
+            .command(script)
+            .args(arguments.iter().copied())
+            .run()
+    }
+    /// # Execute::run_parallel;
+    /// this method executes several commands at the same time, at most `limit` at once,
+    /// and returns their results in the order they were given together with the
+    /// total time taken. Use `batch::Batch` for fail-fast mode or more settings.
+    /// ###  Example
+    /// ```rust
+    /// use commandcrafter::execute::Execute;
+    /// let out = Execute::run_parallel(&[("ls", &["-l"]), ("uname", &["-a"])], 2);
+    /// println!("done in {:?}", out.elapsed);
+    /// Execute::print_into_console_multiple(out.results);
+    /// ```
+    pub fn run_parallel(commands: &[(&str, &[&str])], limit: usize) -> BatchOutput {
+        Batch::new()
+            .limit(limit)
+            .extend(commands.iter().map(|(command, arguments)| {
+                CommandSpec::new(*command).args(arguments.iter().copied())
+            }))
+            .run()
+    }
+    /// # Execute::stream;
+    /// this method executes the command and calls `on_event` for every line of
+    /// stdout and stderr as soon as it is printed, while still collecting the whole output,
+    /// it is a shortcut for `CommandSpec::new(command).args(arguments).stream(on_event)`
+    /// ###  Example
+    /// ```rust
+    /// use commandcrafter::execute::{Execute, OutputEvent};
+    /// let out = Execute::stream("ls", &["-l"], |event| match event {
+    ///     OutputEvent::Stdout(line) => println!("{}", line),
+    ///     OutputEvent::Stderr(line) => eprintln!("{}", line),
+    ///     OutputEvent::Exit(_) => {}
+    /// });
+    /// let _ = Execute::write_to_file(&out);
+    /// ```
+    pub fn stream<F>(
+        command: &str,
+        arguments: &[&str],
+        on_event: F,
+    ) -> Result<ExecOutput, CommandError>
+    where
+        F: FnMut(&OutputEvent),
+    {
+        CommandSpec::new(command)
+            .args(arguments.iter().copied())
+            .stream(on_event)
     }
     /// Displays the output of a shell command in the console.
     ///
     /// # Arguments
     ///
-    /// * `output` - The output of the command as a `Result<Vec<u8>, String>`.
-    ///              If the result is `Ok`, it contains the output data as a vector of unsigned 8-bit integers (bytes).
-    ///              If the result is `Err`, it contains the error message as a string.
+    /// * `output` - The output of the command as a `Result<ExecOutput, CommandError>`.
+    ///   If the result is `Ok`, its stdout is printed.
+    ///   If the result is `Err`, its error message is printed.
     ///
     /// # Returns
     ///
@@ -139,24 +1008,21 @@ impl Execute {
     /// // Print the output
     /// Execute::print_into_console(out);
     /// ```
-    pub fn print_into_console(output: Result<Vec<u8>, String>) {
+    pub fn print_into_console(output: Result<ExecOutput, CommandError>) {
         match output {
-            Ok(bytes) => {
-                let formatting = format!("{}", String::from_utf8_lossy(&bytes));
-                println!("{}", formatting);
-            }
+            Ok(out) => println!("{}", out.stdout_str()),
             Err(err) => eprintln!("Error: {}", err),
         }
     }
 
     /// Print the outputs of multiple shell commands into the console.
     ///
-    /// This function takes a vector of `Result<Vec<u8>, String>` representing the outputs of shell commands.
+    /// This function takes a vector of `Result<ExecOutput, CommandError>` representing the outputs of shell commands.
     /// For each output, it prints the content to the console if it's successful, otherwise, it prints the error message.
     ///
     /// # Arguments
     ///
-    /// * `outputs` - A vector of `Result<Vec<u8>, String>` representing the outputs of shell commands.
+    /// * `outputs` - A vector of `Result<ExecOutput, CommandError>` representing the outputs of shell commands.
     ///
     /// # Examples
     ///
@@ -168,7 +1034,7 @@ impl Execute {
     /// let outputs = vec![out1, out2];
     /// Execute::print_into_console_multiple(outputs);
     /// ```
-    pub fn print_into_console_multiple(outputs: Vec<Result<Vec<u8>, String>>) {
+    pub fn print_into_console_multiple(outputs: Vec<Result<ExecOutput, CommandError>>) {
         for output in outputs {
             Execute::print_into_console(output)
         }
@@ -177,13 +1043,13 @@ impl Execute {
     ///
     /// # Arguments
     ///
-    /// * `content` - The output of the command (could be a combination of commands) as a `Result<Vec<u8>, String>`.
-    ///               If the result is `Ok`, it contains the output data as a vector of unsigned 8-bit integers (bytes).
-    ///               If the result is `Err`, it contains the error message as a string.
+    /// * `content` - The output of the command (could be a combination of commands) as a `Result<ExecOutput, CommandError>`.
+    ///   If the result is `Ok`, its stdout and stderr are written.
+    ///   If the result is `Err`, its error message is printed.
     ///
     /// # Returns
     ///
-    /// * `std::io::Result<()>` - The result of writing the command output into the file named `ExecuteLog.lg`.
+    /// * `Result<(), CommandError>` - The result of writing the command output into the file named `ExecuteLog.log`.
     ///
     /// # Example
     ///```rust
@@ -191,11 +1057,10 @@ impl Execute {
     /// let out = Execute::run("ls", &["-l"]);
     /// let _ = Execute::write_to_file(&out);
     /// ```
-    pub fn write_to_file(content: &Result<Vec<u8>, String>) -> std::io::Result<()> {
+    pub fn write_to_file(content: &Result<ExecOutput, CommandError>) -> Result<(), CommandError> {
         match content {
-            Ok(output_cmd) => {
-                let mut file = File::create(FILE_NAME)?;
-                file.write_all(output_cmd)?;
+            Ok(_) => {
+                Execute::filestore().write(std::slice::from_ref(content))?;
             }
             Err(e) => println!("Error {}", e),
         }
@@ -204,16 +1069,16 @@ impl Execute {
 
     /// Write the combined output of shell commands to a file.
     ///
-    /// This function takes a vector of `Result<Vec<u8>, String>` representing the outputs of shell commands.
-    /// It iterates over each output and writes it to the file named `ExecuteLog.lg` using the `write_to_file` function.
+    /// This function takes a vector of `Result<ExecOutput, CommandError>` representing the outputs of shell commands.
+    /// It iterates over each output and appends it to the file named `ExecuteLog.log` of the current folder.
     ///
     /// # Arguments
     ///
-    /// * `outputs` - A vector of `Result<Vec<u8>, String>` representing the outputs of shell commands.
+    /// * `outputs` - A vector of `Result<ExecOutput, CommandError>` representing the outputs of shell commands.
     ///
     /// # Returns
     ///
-    /// * `std::io::Result<()>` - The result of writing the combined command outputs into the file named `ExecuteLog.lg`.
+    /// * `Result<(), CommandError>` - The result of writing the combined command outputs into the file named `ExecuteLog.log`.
     ///
     /// # Example
     ///
@@ -230,34 +1095,25 @@ impl Execute {
     /// // Write the combined outputs to a file
     /// let _ = Execute::write_combined_to_file(combined_outputs).unwrap();
     /// ```
-
-    pub fn write_combined_to_file(outputs: &[Result<Vec<u8>, String>]) -> std::io::Result<()> {
-        // Open the file in append mode or create it if it doesn't exist
-        let mut file = std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(FILE_NAME)?;
-
-        // Write each output to the file
-        for (i, output) in outputs.iter().enumerate() {
-            match output {
-                Ok(output_cmd) => {
-                    file.write_all(output_cmd)?;
-                }
-                Err(e) => {
-                    // Handle error
-                    println!("Error: {}", e);
-                }
-            }
-
-            // Insert a newline character after each command output unless it's the last command
-            if i < outputs.len() - 1 {
-                file.write_all(b"\n")?;
-            }
+    pub fn write_combined_to_file(
+        outputs: &[Result<ExecOutput, CommandError>],
+    ) -> Result<(), CommandError> {
+        for e in outputs.iter().filter_map(|output| output.as_ref().err()) {
+            println!("Error: {}", e);
         }
-
+        Execute::filestore().append(true).write(outputs)?;
         Ok(())
     }
+
+    /// the store `write_to_file` and `write_combined_to_file` use: `ExecuteLog.log`
+    /// in the current folder, holding only what the commands printed
+    fn filestore() -> Filestore {
+        Filestore::new(".")
+            .filename(DEFAULT_FILENAME)
+            .overwrite(true)
+            .no_header()
+    }
+
     /// `check_operation` Check the operation of the file
     /// # Arguments
     /// * `op` - the result of file creation
@@ -273,15 +1129,14 @@ impl Execute {
     /// let res = Execute::write_combined_to_file(cmb);
     /// Execute::check_operation(&res);
     /// ```
-    pub fn check_operation(op: &std::io::Result<()>) -> bool {
+    pub fn check_operation(op: &Result<(), CommandError>) -> bool {
         match op {
             Ok(_) => {
-                // println!("File created successfully in {}.", FILE_NAME);
-                println!("File created successfully in {}.", FILE_NAME);
+                println!("File created successfully in {}.", DEFAULT_FILENAME);
                 true
             }
             Err(_) => {
-                panic!("Failed to create file {}.", FILE_NAME);
+                panic!("Failed to create file {}.", DEFAULT_FILENAME);
             }
         }
     }
@@ -6,106 +6,464 @@
 //! Therefore, this module will has some functionalities such
 //! - write the output inside a file named `ExecuteLog.lg`
 //! - check if the file exists
-//! - store the file inside a folder (usually the home/Desktop directory)
+//! - store the file inside a folder (`$XDG_STATE_HOME/commandcrafter/logs` unless told otherwise)
 //! - combine multiple outputs into one file
+//! - choose the file name, append mode, permissions, newlines and headers of a log
 //! - the ability to delete the file (optional)
 use crate::color::Col;
+use crate::error::CommandError;
+use crate::execute::{is_dry_run, print_dry_run};
+use crate::interactive::Transcript;
+use crate::output::ExecOutput;
+use crate::shellwords;
 use std::env;
 use std::fs;
 use std::io::Write;
-/// struct for filestore implementation
-pub struct Filestore;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+/// The environment variable naming the folder the logs are stored in, over the default one.
+pub const LOG_DIR_ENV: &str = "COMMANDCRAFTER_LOG_DIR";
+
+/// The name of the log `Execute::write_to_file` and `Execute::write_combined_to_file` write into.
+pub const DEFAULT_FILENAME: &str = "ExecuteLog.log";
+/// The name of the log `Filestore::write` writes into unless told otherwise, a file
+/// of its own for every run inside a folder for every day.
+pub const DEFAULT_TEMPLATE: &str = "{date}/{command}-{time}-{pid}.log";
+/// The header written before the output of every command unless told otherwise.
+pub const DEFAULT_HEADER: &str = "$ {command}";
+
+/// # Filestore
+/// Stores the output of commands into log files inside a folder.
+///
+/// `Filestore::default()` uses `Filestore::log_dir`, `Filestore::new` any other folder.
+/// The builder methods set how the logs are written, a configured store is a plain
+/// value that can be cloned, kept in a struct and reused for every log:
+///
+/// * `filename`:    the file `write` writes into, `{date}/{command}-{time}-{pid}.log` by default
+/// * `append`:      append to the file instead of writing a new one
+/// * `overwrite`:   replace the file when it already exists
+/// * `permissions`: the unix mode of the files written
+/// * `newline`:     whether every output ends with a newline
+/// * `header`:      the line written before the output of every command
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::execute::Execute;
+/// use commandcrafter::filestore::Filestore;
+///
+/// let dir = std::env::temp_dir().join("crafter-doc-logs");
+/// let _ = std::fs::remove_dir_all(&dir);
+/// let store = Filestore::new(&dir);
+/// let path = store.write_log(&[Execute::run("echo", &["hello"])], "echo.log").unwrap();
+/// assert_eq!(path, dir.join("echo.log"));
+/// // an existing log is never replaced unless the store overwrites
+/// let path = store.write_log(&[Execute::run("echo", &["again"])], "echo.log").unwrap();
+/// assert_eq!(path, dir.join("echo-1.log"));
+/// ```
+///
+/// Every run in a file of its own:
+/// ```rust
+/// use commandcrafter::execute::Execute;
+/// use commandcrafter::filestore::Filestore;
+///
+/// let store = Filestore::new(std::env::temp_dir().join("crafter-doc-runs"))
+///     .filename("{date}/{command}-{time}-{pid}.log");
+/// let out = Execute::run("/usr/bin/env", &["echo", "hi"]);
+/// let pid = out.as_ref().unwrap().pid;
+/// let path = store.write(&[out]).unwrap();
+/// let name = path.file_name().unwrap().to_str().unwrap();
+/// assert!(name.starts_with("env-"));
+/// assert!(name.ends_with(&format!("-{}.log", pid)));
+/// ```
+///
+/// A private log every run appends to, with the exit status in the headers:
+/// ```rust
+/// use commandcrafter::execute::Execute;
+/// use commandcrafter::filestore::{Filestore, Newline};
+///
+/// let dir = std::env::temp_dir().join("crafter-doc-configured");
+/// let _ = std::fs::remove_dir_all(&dir);
+/// let store = Filestore::new(&dir)
+///     .filename("printf.log")
+///     .append(true)
+///     .permissions(0o600)
+///     .newline(Newline::Ensure)
+///     .header("## {command} ({status})");
+/// store.write(&[Execute::run("printf", &["one"])]).unwrap();
+/// let path = store.write(&[Execute::run("printf", &["two"])]).unwrap();
+/// assert_eq!(
+///     std::fs::read_to_string(path).unwrap(),
+///     "## printf one (exit 0)\none\n\n## printf two (exit 0)\ntwo\n"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filestore {
+    dir: PathBuf,
+    filename: PathBuf,
+    append: bool,
+    overwrite: bool,
+    permissions: Option<u32>,
+    newline: Newline,
+    header: Option<String>,
+}
+
+/// Whether `Filestore` completes the outputs that don't end with a newline.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Newline {
+    /// write stdout and stderr exactly as the command printed them
+    #[default]
+    Keep,
+    /// end stdout and stderr with a newline when the command didn't, so the
+    /// next line of the log never starts in the middle of theirs
+    Ensure,
+}
+
+impl Default for Filestore {
+    fn default() -> Self {
+        Filestore::new(Filestore::log_dir())
+    }
+}
 
 impl Filestore {
-    /// Store the output of a shell command into the folder `desktop/log/ExecuteLog`.
+    /// Store the logs inside `dir`, which is created when needed.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Filestore {
+            dir: dir.into(),
+            filename: PathBuf::from(DEFAULT_TEMPLATE),
+            append: false,
+            overwrite: false,
+            permissions: None,
+            newline: Newline::Keep,
+            header: Some(DEFAULT_HEADER.to_string()),
+        }
+    }
+
+    /// The file `write` writes into, `{date}/{command}-{time}-{pid}.log` by default.
+    ///
+    /// A relative path is taken inside the folder and may go into subfolders,
+    /// which are created when needed; an absolute path is used as it is.
+    ///
+    /// The name is a template, these placeholders are filled in when writing:
+    ///
+    /// * `{command}`: the name of the program run by the first command, without its
+    ///   folder and with anything but letters, digits, `-`, `_` and `.` replaced by `_`
+    /// * `{pid}`:     the process id of the first command
+    /// * `{date}`:    the local date, like `2024-05-31`
+    /// * `{time}`:    the local time, like `14-03-59`
+    pub fn filename(mut self, filename: impl Into<PathBuf>) -> Self {
+        self.filename = filename.into();
+        self
+    }
+
+    /// Append to the log files instead of writing new ones, off by default.
+    ///
+    /// The outputs written are separated from what the file already held by an empty line.
+    pub fn append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    /// Replace the log files that already exist, off by default.
+    ///
+    /// Otherwise a number is added to the name of the file written instead,
+    /// `build.log` becomes `build-1.log`, then `build-2.log`.
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// The permissions of the files written, like `0o600` to keep them private.
+    ///
+    /// They are set on every file written, whatever the umask, and ignored outside unix.
+    pub fn permissions(mut self, mode: u32) -> Self {
+        self.permissions = Some(mode);
+        self
+    }
+
+    /// Whether the outputs that don't end with a newline get one, `Newline::Keep` by default.
+    pub fn newline(mut self, newline: Newline) -> Self {
+        self.newline = newline;
+        self
+    }
+
+    /// The line written before the output of every command, `$ {command}` by default.
+    ///
+    /// `{command}` is replaced by the quoted command line, `{pid}` by the process id,
+    /// `{status}` by `exit <code>` or `signal <number>` and `{duration}` by how long
+    /// the command ran.
+    pub fn header(mut self, format: impl Into<String>) -> Self {
+        self.header = Some(format.into());
+        self
+    }
+
+    /// Write the outputs without any header line.
+    pub fn no_header(mut self) -> Self {
+        self.header = None;
+        self
+    }
+
+    /// The folder the logs are stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The path of the log `filename`: inside the folder when it is relative, itself otherwise.
+    pub fn path(&self, filename: impl AsRef<Path>) -> PathBuf {
+        self.dir.join(filename)
+    }
+
+    /// The folder the logs are stored in by default, the first one of:
     ///
-    /// This method creates a folder named `logs` on the user's desktop (if it doesn't already exist)
-    /// and stores the output of the command into a file named `ExecuteLog.lg` within that folder.
+    /// * `$COMMANDCRAFTER_LOG_DIR`
+    /// * `$XDG_STATE_HOME/commandcrafter/logs`
+    /// * `$HOME/.local/state/commandcrafter/logs` (`%LOCALAPPDATA%\commandcrafter\logs` on windows)
+    /// * `commandcrafter/logs` inside the temporary folder, when none of the variables are set
+    ///
+    /// Empty variables are ignored, like relative paths in the XDG ones.
+    pub fn log_dir() -> PathBuf {
+        if let Some(dir) = env_dir(LOG_DIR_ENV, false) {
+            return dir;
+        }
+        let state = env_dir("XDG_STATE_HOME", true)
+            .or_else(|| {
+                if cfg!(windows) {
+                    env_dir("LOCALAPPDATA", true)
+                } else {
+                    env_dir("HOME", true).map(|home| home.join(".local").join("state"))
+                }
+            })
+            .unwrap_or_else(env::temp_dir);
+        state.join("commandcrafter").join("logs")
+    }
+
+    /// Write the outputs of commands into the file set with `filename`, see `write_log`.
+    ///
+    /// # Example
+    ///```rust
+    /// use commandcrafter::execute::Execute;
+    /// use commandcrafter::filestore::Filestore;
+    ///
+    /// let store = Filestore::default().filename("uname.log").no_header();
+    /// let path = store.write(&[Execute::run("uname", &[])]).unwrap();
+    /// assert!(!std::fs::read_to_string(path).unwrap().starts_with("$ uname"));
+    /// ```
+    pub fn write(
+        &self,
+        outputs: &[Result<ExecOutput, CommandError>],
+    ) -> Result<PathBuf, CommandError> {
+        self.write_log(outputs, &self.filename)
+    }
+
+    /// Write the outputs of commands into `filename`, without printing anything.
+    ///
+    /// The outputs are separated by an empty line. An existing file gets a number added to
+    /// the name written instead, unless the store appends or overwrites.
     ///
     /// # Arguments
     ///
-    /// * `content`:   The output of the command as a `Result<Vec<u8>, String>`.
-    ///                If the result is `Ok`, it contains the output data as a vector of unsigned 8-bit integers (bytes).
-    ///                If the result is `Err`, it contains the error message as a string.
-    /// * `filename`:  The name of the file log that holds the output of single command the name should be str type
-    ///                the name of file log should follow this pattern `/filename.log`
+    /// * `outputs`:  The outputs to store: the header, stdout, stderr, then the error message
+    ///   of the ones that failed.
+    /// * `filename`: The name of the file log, like `build.log`, a template like the ones of
+    ///   `Filestore::filename`.
     ///
     /// # Returns
     ///
-    /// * `std::io::Result<()>` - The result of writing the command output into the file named `ExecuteLog.lg`.
+    /// * `Result<PathBuf, CommandError>` - The path of the file that was written.
     ///
     /// # Example
     ///```rust
     /// use commandcrafter::execute::Execute;
     /// use commandcrafter::filestore::Filestore;
     ///
-    /// let out = Execute::run("ls", &["-l"]);
-    /// Filestore::write_into_desktop(&out, "/lscmd.log").unwrap();
+    /// let out = Execute::run("echo", &["hello"]);
+    /// let path = Filestore::default().write_log(&[out], "echo.log").unwrap();
+    /// assert!(std::fs::read_to_string(path).unwrap().starts_with("$ echo hello"));
     /// ```
-    pub fn write_into_desktop(
-        content: &Result<Vec<u8>, String>,
-        filename: &str,
-    ) -> std::io::Result<()> {
-        let log_folder = env::var("HOME").unwrap() + "/Desktop/logs";
-        // Create the folder
-        match fs::create_dir_all(&log_folder) {
-            Ok(_) => {
-                println!(
-                    "{}",
-                    Col::print_col(&Col::Green, "The folder was created successfully")
-                );
-                let file_log = log_folder + filename;
-                let fl = fs::File::create(&file_log);
-                match fl {
-                    Ok(mut f) => {
-                        println!(
-                            "{}: {}",
-                            Col::print_col(
-                                &Col::Green,
-                                "The file log was created successfully with in"
-                            ),
-                            file_log
-                        );
-
-                        // match the content of the command output
-                        match content {
-                            Ok(output_cmd) => {
-                                f.write_all(output_cmd)?;
-                            }
-                            Err(e) => println!("Error {}", e),
-                        }
-                        // f.write_all(content)?;
-                    }
-                    Err(e) => {
-                        println!(
-                            "{}: {}",
-                            Col::print_col(&Col::Red, "The file couldn't be created"),
-                            e
-                        );
-                    }
-                }
+    pub fn write_log(
+        &self,
+        outputs: &[Result<ExecOutput, CommandError>],
+        filename: impl AsRef<Path>,
+    ) -> Result<PathBuf, CommandError> {
+        let (command, pid) = outputs
+            .iter()
+            .find_map(|output| match output {
+                Ok(output) => Some(output),
+                Err(e) => e.output(),
+            })
+            .map_or(("", None), |output| {
+                (output.command.as_str(), Some(output.pid))
+            });
+        let file_log = self.path(render_filename(filename.as_ref(), command, pid));
+        if is_dry_run() {
+            let file_log = self.target(file_log);
+            preview_write(&file_log, self.append);
+            return Ok(file_log);
+        }
+        let (mut f, file_log) = self.open(file_log)?;
+        let mut first = f.metadata()?.len() == 0;
+        for output in outputs {
+            if !first {
+                writeln!(f)?;
             }
-            Err(e) => {
-                println!("Couldn't create the folder: {}", e);
+            first = false;
+            write_entry(&mut f, output, self.header.as_deref(), self.newline)?;
+        }
+        Ok(file_log)
+    }
+
+    /// Write the transcript of an interactive session into `filename`.
+    ///
+    /// An existing file is kept like `write_log` does, `{pid}` is the process id of this program.
+    ///
+    /// # Arguments
+    ///
+    /// * `transcript`: What was sent to and received from the command, see `Interactive::transcript`.
+    /// * `filename`:   The name of the file log, like `pacman-install.log`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<PathBuf, CommandError>` - The path of the file that was written.
+    ///
+    /// # Example
+    ///```rust
+    /// use commandcrafter::execute::CommandSpec;
+    /// use commandcrafter::filestore::Filestore;
+    ///
+    /// let mut session = CommandSpec::new("sh")
+    ///     .args(["-c", "read name; echo hello $name"])
+    ///     .interactive()
+    ///     .unwrap();
+    /// session.send_line("crafter").unwrap();
+    /// session.expect("hello").unwrap();
+    /// let store = Filestore::default();
+    /// let path = store.write_transcript(session.transcript(), "greeting.log").unwrap();
+    /// let log = std::fs::read_to_string(path).unwrap();
+    /// assert!(log.contains("> crafter\n< hello crafter"));
+    /// ```
+    pub fn write_transcript(
+        &self,
+        transcript: &Transcript,
+        filename: impl AsRef<Path>,
+    ) -> Result<PathBuf, CommandError> {
+        let filename = render_filename(filename.as_ref(), transcript.command(), None);
+        let file_log = self.path(filename);
+        if is_dry_run() {
+            let file_log = self.target(file_log);
+            preview_write(&file_log, self.append);
+            return Ok(file_log);
+        }
+        let (mut f, file_log) = self.open(file_log)?;
+        f.write_all(transcript.to_string().as_bytes())?;
+        Ok(file_log)
+    }
+
+    /// open `path` for writing the way the store is configured, creating its folders,
+    /// along with the path of the file opened
+    fn open(&self, path: PathBuf) -> std::io::Result<(fs::File, PathBuf)> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true);
+        if self.append {
+            options.create(true).append(true);
+        } else if self.overwrite {
+            options.create(true).truncate(true);
+        } else {
+            // fails instead of opening a file created in the meantime
+            options.create_new(true);
+        }
+        let mut opened = path.clone();
+        let mut n = 0;
+        let f = loop {
+            match options.open(&opened) {
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    n += 1;
+                    opened = numbered(&path, n);
+                }
+                f => break f?,
             }
+        };
+        #[cfg(unix)]
+        if let Some(mode) = self.permissions {
+            use std::os::unix::fs::PermissionsExt;
+            f.set_permissions(fs::Permissions::from_mode(mode))?;
+        }
+        Ok((f, opened))
+    }
+
+    /// the file `open` would write for `path`, without creating anything
+    fn target(&self, path: PathBuf) -> PathBuf {
+        if self.append || self.overwrite {
+            return path;
         }
+        (0..)
+            .map(|n| {
+                if n == 0 {
+                    path.clone()
+                } else {
+                    numbered(&path, n)
+                }
+            })
+            .find(|candidate| !candidate.exists())
+            .expect("some number is free")
+    }
 
+    /// Store the output of a shell command into a file of the default log folder.
+    ///
+    /// This method creates the folder `Filestore::log_dir` (if it doesn't already exist)
+    /// and stores the output of the command into the file named `filename` within that folder,
+    /// printing what it did. `Filestore::default().write_log` does the same silently.
+    ///
+    /// # Arguments
+    ///
+    /// * `content`:   The output of the command as a `Result<ExecOutput, CommandError>`.
+    ///   If the result is `Ok`, its command line, stdout and stderr are stored.
+    ///   If the result is `Err`, whatever the command printed and the error message are stored.
+    /// * `filename`:  The name of the file log that holds the output of single command, like `lscmd.log`
+    ///   or `{command}-{time}.log`, see `Filestore::filename`. A leading `/` is ignored, older
+    ///   versions required one. An existing file is kept, a number is added to the new name.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), CommandError>` - The result of writing the command output into the file.
+    ///
+    /// # Example
+    ///```rust
+    /// use commandcrafter::execute::Execute;
+    /// use commandcrafter::filestore::Filestore;
+    ///
+    /// let out = Execute::run("ls", &["-l"]);
+    /// Filestore::write_into_desktop(&out, "lscmd.log").unwrap();
+    /// ```
+    pub fn write_into_desktop(
+        content: &Result<ExecOutput, CommandError>,
+        filename: &str,
+    ) -> Result<(), CommandError> {
+        let store = Filestore::default();
+        let filename = filename.trim_start_matches('/');
+        report(store.write_log(std::slice::from_ref(content), filename))?;
+        if let Err(e) = content {
+            println!("Error {}", e);
+        }
         Ok(())
     }
-    /// Write the output of a shell command to a file on the desktop.
+    /// Store the outputs of shell commands into a file of their own in the default log folder.
     ///
-    /// This method creates a folder named `logs` on the user's desktop (if it doesn't already exist)
-    /// and stores the output of the command into a file named `ExecuteLog.lg` within that folder.
+    /// This method creates the folder `Filestore::log_dir` (if it doesn't already exist)
+    /// and writes the outputs into `{date}/{command}-{time}-{pid}.log` within that folder,
+    /// named after the first command, printing what it did. `Filestore::default().write`
+    /// does the same silently.
     ///
     /// # Arguments
     ///
-    /// * `content` - The output of the command as a `Result<Vec<u8>, String>`.
-    ///               If the result is `Ok`, it contains the output data as a vector of unsigned 8-bit integers (bytes).
-    ///               If the result is `Err`, it contains the error message as a string.
+    /// * `content` - The output of the command as a `Result<ExecOutput, CommandError>`.
+    ///   If the result is `Ok`, its command line, stdout and stderr are stored.
+    ///   If the result is `Err`, whatever the command printed and the error message are stored.
     ///
     /// # Returns
     ///
-    /// * `std::io::Result<()>` - The result of writing the command output into the file named `ExecuteLog.lg`.
+    /// * `Result<(), CommandError>` - The result of writing the command outputs into the file.
     ///
     /// # Example
     /// ```rust
@@ -118,51 +476,243 @@ impl Filestore {
     /// // Combine the outputs into a single vector
     /// let combined_outputs = &[out1, out2];
     ///
-    /// // Write the combined outputs to the log file on the desktop
+    /// // Write the combined outputs to the log file
     /// let _ = Filestore::write_combined_to_desktop_log(combined_outputs).unwrap();
     /// ```
     pub fn write_combined_to_desktop_log(
-        outputs: &[Result<Vec<u8>, String>],
-    ) -> std::io::Result<()> {
-        let log_folder = env::var("HOME").unwrap() + "/Desktop/logs";
-
-        // Create the folder
-        match fs::create_dir_all(&log_folder) {
-            Ok(_) => {
-                println!(
-                    "{}",
-                    Col::print_col(&Col::Green, "The folder was created successfully")
-                );
-                let file_log = log_folder + "/ExecuteLog.log";
-                let mut f = fs::OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open(&file_log)?;
-                println!(
-                    "{}: {}",
-                    Col::print_col(&Col::Green, "The file log was created successfully within"),
-                    file_log
-                );
-
-                // Iterate over the content and match the content of the command output
-                for output in outputs {
-                    match output {
-                        Ok(out_cmd) => {
-                            f.write_all(out_cmd)?;
-                            // Add a newline character after each output
-                            writeln!(f)?;
-                        }
-                        Err(e) => {
-                            println!("Error: {}", e);
-                        }
-                    }
-                }
-            }
-            Err(e) => {
-                println!("Couldn't create the folder: {}", e);
-            }
+        outputs: &[Result<ExecOutput, CommandError>],
+    ) -> Result<(), CommandError> {
+        report(Filestore::default().write(outputs))?;
+        for e in outputs.iter().filter_map(|output| output.as_ref().err()) {
+            println!("Error: {}", e);
         }
-
         Ok(())
     }
 }
+
+/// print where a log was written, or why it couldn't be
+fn report(written: Result<PathBuf, CommandError>) -> Result<(), CommandError> {
+    if is_dry_run() {
+        return written.map(drop);
+    }
+    match written {
+        Ok(path) => {
+            println!(
+                "{}: {}",
+                Col::print_col(&Col::Green, "The file log was written successfully in"),
+                path.display()
+            );
+            Ok(())
+        }
+        Err(e) => {
+            println!(
+                "{}: {}",
+                Col::print_col(&Col::Red, "The file log couldn't be written"),
+                e
+            );
+            Err(e)
+        }
+    }
+}
+
+/// the folder named by the variable `key` when it is set and not empty, `absolute` also rejects relative paths
+fn env_dir(key: &str, absolute: bool) -> Option<PathBuf> {
+    let dir = PathBuf::from(env::var_os(key)?);
+    let usable = !dir.as_os_str().is_empty() && (!absolute || dir.is_absolute());
+    usable.then_some(dir)
+}
+
+/// Print the folders that writing `path` would create and what would happen to the file,
+/// used instead of writing it in dry-run mode.
+pub(crate) fn preview_write(path: &Path, append: bool) {
+    let missing: Vec<&Path> = path
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+        .collect();
+    for dir in missing.iter().rev() {
+        print_dry_run(&format!("would create the folder {}", dir.display()));
+    }
+    let action = match (path.exists(), append) {
+        (false, _) => "create",
+        (true, true) => "append to",
+        (true, false) => "overwrite",
+    };
+    print_dry_run(&format!("would {} the file {}", action, path.display()));
+}
+
+/// Write the output of a command into a log: the quoted command line, stdout,
+/// then stderr, then the error message if it failed.
+///
+/// A retried command gets a `# attempt n/total` line before the output of every attempt.
+pub(crate) fn write_output<W: Write>(
+    w: &mut W,
+    content: &Result<ExecOutput, CommandError>,
+) -> std::io::Result<()> {
+    write_entry(w, content, Some(DEFAULT_HEADER), Newline::Keep)
+}
+
+/// `write_output` with the header and newline policy of a `Filestore`
+fn write_entry<W: Write>(
+    w: &mut W,
+    content: &Result<ExecOutput, CommandError>,
+    header: Option<&str>,
+    newline: Newline,
+) -> std::io::Result<()> {
+    let (output, error) = match content {
+        Ok(output) => (Some(output), None),
+        Err(e) => (e.output(), Some(e)),
+    };
+    let stream = |w: &mut W, bytes: &[u8]| {
+        w.write_all(bytes)?;
+        if newline == Newline::Ensure && !bytes.is_empty() && !bytes.ends_with(b"\n") {
+            writeln!(w)?;
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    if let Some(output) = output {
+        if let Some(header) = header {
+            writeln!(w, "{}", render_header(header, output))?;
+        }
+        let total = output.attempts.len() + 1;
+        for (i, attempt) in output.attempts.iter().enumerate() {
+            writeln!(w, "# attempt {}/{}", i + 1, total)?;
+            stream(w, &attempt.output.stdout)?;
+            stream(w, &attempt.output.stderr)?;
+            writeln!(
+                w,
+                "# attempt {} failed after {:.2?}: {}, retrying in {:.2?}",
+                i + 1,
+                attempt.output.duration,
+                attempt.reason,
+                attempt.delay
+            )?;
+        }
+        if total > 1 {
+            writeln!(w, "# attempt {}/{}", total, total)?;
+        }
+        stream(w, &output.stdout)?;
+        stream(w, &output.stderr)?;
+    }
+    if let Some(e) = error {
+        writeln!(w, "Error: {}", e)?;
+    }
+    Ok(())
+}
+
+/// fill the placeholders of a header with what `output` holds
+fn render_header(format: &str, output: &ExecOutput) -> String {
+    let status = match (output.code(), output.signal) {
+        (Some(code), _) => format!("exit {}", code),
+        (None, Some(signal)) => format!("signal {}", signal),
+        (None, None) => "unknown".to_string(),
+    };
+    format
+        .replace("{command}", &output.command)
+        .replace("{pid}", &output.pid.to_string())
+        .replace("{status}", &status)
+        .replace("{duration}", &format!("{:.2?}", output.duration))
+}
+
+/// fill the placeholders of a log name, see `Filestore::filename`
+pub(crate) fn render_filename(template: &Path, command: &str, pid: Option<u32>) -> PathBuf {
+    let Some(template) = template.to_str().filter(|t| t.contains('{')) else {
+        return template.to_path_buf();
+    };
+    let (date, time) = timestamp();
+    let pid = pid.unwrap_or_else(std::process::id);
+    PathBuf::from(
+        template
+            .replace("{command}", &command_name(command))
+            .replace("{pid}", &pid.to_string())
+            .replace("{date}", &date)
+            .replace("{time}", &time),
+    )
+}
+
+/// the name of the program of a command line, safe to use in a file name
+pub(crate) fn command_name(command_line: &str) -> String {
+    let words = shellwords::split(command_line).unwrap_or_default();
+    let program = words
+        .first()
+        .and_then(|word| word.rsplit(['/', '\\']).next())
+        .unwrap_or("");
+    let name: String = program
+        .chars()
+        .take(64)
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    // no hidden files, no `.` or `..`
+    let name = name.trim_start_matches('.');
+    if name.is_empty() {
+        "command".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// `path` with `-n` added to the name of the file, before its extension
+fn numbered(path: &Path, n: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+/// the local date and time as `YYYY-MM-DD` and `HH-MM-SS`, in UTC when the time zone is unknown
+fn timestamp() -> (String, String) {
+    let [year, month, day, hour, min, sec] = local_time().unwrap_or_else(utc_time);
+    (
+        format!("{:04}-{:02}-{:02}", year, month, day),
+        format!("{:02}-{:02}-{:02}", hour, min, sec),
+    )
+}
+
+#[cfg(unix)]
+fn local_time() -> Option<[i64; 6]> {
+    // SAFETY: time(2) accepts a null pointer, localtime_r(3) only writes into `tm`
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            return None;
+        }
+        tm
+    };
+    Some([
+        i64::from(tm.tm_year) + 1900,
+        i64::from(tm.tm_mon) + 1,
+        i64::from(tm.tm_mday),
+        i64::from(tm.tm_hour),
+        i64::from(tm.tm_min),
+        i64::from(tm.tm_sec),
+    ])
+}
+
+#[cfg(not(unix))]
+fn local_time() -> Option<[i64; 6]> {
+    None
+}
+
+fn utc_time() -> [i64; 6] {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64);
+    let (days, rest) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    // the civil date of a day count, from Howard Hinnant's `civil_from_days`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    [year, month, day, rest / 3600, rest % 3600 / 60, rest % 60]
+}
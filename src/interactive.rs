@@ -0,0 +1,351 @@
+//! # Interactive module
+//! Talk to a running command: write to its stdin a piece at a time while its
+//! output is read line by line, or wait for a prompt matching a regular
+//! expression before answering it, the way `expect` does. Stdout and stderr are
+//! drained on threads of their own, so a command printing a lot never blocks on
+//! a full pipe while we are writing to it.
+//!
+//! Everything sent and received is kept in a `Transcript`, which
+//! `Filestore::write_transcript` stores next to the other logs.
+
+use crate::error::CommandError;
+use crate::output::ExecOutput;
+use crate::process::{Pipe, Polled, Running};
+use regex::bytes::Regex;
+use std::fmt;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// How long `expect` waits for its pattern unless told otherwise.
+pub const DEFAULT_EXPECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// # Interactive
+/// A running command with its stdin open, see `CommandSpec::interactive`.
+///
+/// `read_line` returns what the command prints on stdout line by line, `expect`
+/// waits until it prints something matching a pattern. Both consume the output
+/// they return, the next call starts right after it. `finish` closes stdin and
+/// waits for the command.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::execute::CommandSpec;
+///
+/// let mut bc = CommandSpec::new("sh")
+///     .args(["-c", "while read a b; do echo $((a * b)); done"])
+///     .interactive()
+///     .unwrap();
+/// bc.send_line("6 7").unwrap();
+/// assert_eq!(bc.read_line().as_deref(), Some("42"));
+/// bc.send_line("3 5").unwrap();
+/// assert_eq!(bc.read_line().as_deref(), Some("15"));
+/// let out = bc.finish().unwrap();
+/// assert_eq!(out.stdout_str(), "42\n15\n");
+/// ```
+///
+/// Answering the questions of an installer:
+/// ```rust
+/// use commandcrafter::execute::CommandSpec;
+/// use std::time::Duration;
+///
+/// let script = r#"printf ':: Proceed with installation? [Y/n] '; read answer
+/// [ "$answer" = y ] && echo "installing 3 packages" || echo aborted"#;
+/// let mut pacman = CommandSpec::new("sh").args(["-c", script]).interactive().unwrap();
+/// pacman.expect(r"\[Y/n\] $").unwrap();
+/// pacman.send_line("y").unwrap();
+/// let found = pacman
+///     .expect_timeout(r"installing (\d+) packages", Duration::from_secs(5))
+///     .unwrap();
+/// assert_eq!(found.groups, [Some("3".to_string())]);
+/// assert!(pacman.expect("never printed").is_err());
+///
+/// let transcript = pacman.transcript().to_string();
+/// assert!(transcript.contains("> y\n"));
+/// pacman.finish().unwrap();
+/// ```
+pub struct Interactive {
+    stdin: Option<Box<dyn Write + Send>>,
+    running: Option<Running>,
+    /// the result of the command, once it finished
+    result: Option<Result<ExecOutput, CommandError>>,
+    /// output received but not returned by `read_line` or `expect` yet
+    unread: Vec<u8>,
+    match_stderr: bool,
+    timeout: Duration,
+    dry_run: bool,
+    transcript: Transcript,
+}
+
+/// What an `expect` found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Match {
+    /// the output received before the match
+    pub before: String,
+    /// the text the pattern matched
+    pub matched: String,
+    /// the capture groups of the pattern, `None` for the ones that didn't participate
+    pub groups: Vec<Option<String>>,
+}
+
+impl Interactive {
+    pub(crate) fn new(
+        command: String,
+        stdin: Option<Box<dyn Write + Send>>,
+        running: Running,
+    ) -> Self {
+        Interactive {
+            stdin,
+            running: Some(running),
+            result: None,
+            unread: Vec::new(),
+            match_stderr: false,
+            timeout: DEFAULT_EXPECT_TIMEOUT,
+            dry_run: false,
+            transcript: Transcript::new(command),
+        }
+    }
+
+    /// a session with a command that only pretends to run, every `expect` matches right away
+    pub(crate) fn dry_run(output: ExecOutput) -> Self {
+        Interactive {
+            stdin: Some(Box::new(io::sink())),
+            running: None,
+            transcript: Transcript::new(output.command.clone()),
+            result: Some(Ok(output)),
+            unread: Vec::new(),
+            match_stderr: false,
+            timeout: DEFAULT_EXPECT_TIMEOUT,
+            dry_run: true,
+        }
+    }
+
+    /// How long `expect` waits for its pattern, 30 seconds by default.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Let `read_line` and `expect` see what the command prints on stderr too,
+    /// many programs print their prompts there. Off by default.
+    pub fn match_stderr(&mut self, enabled: bool) {
+        self.match_stderr = enabled;
+    }
+
+    /// Write `bytes` to the stdin of the command right away.
+    ///
+    /// Fails with a broken pipe once stdin was closed or the command stopped reading it.
+    pub fn send(&mut self, bytes: &[u8]) -> Result<(), CommandError> {
+        let stdin = self
+            .stdin
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "stdin is closed"))?;
+        stdin.write_all(bytes)?;
+        stdin.flush()?;
+        self.transcript.push(Direction::Sent, bytes);
+        Ok(())
+    }
+
+    /// Write `line` followed by a newline to the stdin of the command.
+    pub fn send_line(&mut self, line: &str) -> Result<(), CommandError> {
+        self.send(format!("{}\n", line).as_bytes())
+    }
+
+    /// Close the stdin of the command, so it sees the end of its input.
+    pub fn close_stdin(&mut self) {
+        self.stdin = None;
+    }
+
+    /// Wait for the next line the command prints on stdout, `None` once it exited.
+    ///
+    /// Lines printed on stderr meanwhile are skipped here (unless `match_stderr`
+    /// is on) but kept in the final output.
+    pub fn read_line(&mut self) -> Option<String> {
+        loop {
+            if let Some(pos) = self.unread.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.unread.drain(..=pos).collect();
+                return Some(text(&line[..pos]));
+            }
+            if !self.receive(None) {
+                break;
+            }
+        }
+        // the last line may not end with a newline
+        (!self.unread.is_empty()).then(|| text(&std::mem::take(&mut self.unread)))
+    }
+
+    /// Wait until the command prints something matching the regular expression
+    /// `pattern`, for the timeout set with `set_timeout`.
+    ///
+    /// See `expect_timeout`.
+    pub fn expect(&mut self, pattern: &str) -> Result<Match, CommandError> {
+        self.expect_timeout(pattern, self.timeout)
+    }
+
+    /// Wait at most `timeout` until the command prints something matching the
+    /// regular expression `pattern`.
+    ///
+    /// The output up to the end of the match is consumed. The command keeps running
+    /// when the pattern isn't found: `CommandError::ExpectTimeout` is returned once
+    /// `timeout` passed, `CommandError::ExpectEof` when the command exits first.
+    /// Both hold what was received and not consumed yet.
+    pub fn expect_timeout(
+        &mut self,
+        pattern: &str,
+        timeout: Duration,
+    ) -> Result<Match, CommandError> {
+        let regex = Regex::new(pattern).map_err(|e| CommandError::InvalidPattern {
+            pattern: pattern.to_string(),
+            reason: e.to_string(),
+        })?;
+        if self.dry_run {
+            return Ok(Match::default());
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(found) = self.take_match(&regex) {
+                return Ok(found);
+            }
+            if !self.receive(Some(deadline)) {
+                let pattern = pattern.to_string();
+                let seen = text(&self.unread);
+                return Err(match self.running {
+                    Some(_) => CommandError::ExpectTimeout {
+                        pattern,
+                        after: timeout,
+                        seen,
+                    },
+                    None => CommandError::ExpectEof { pattern, seen },
+                });
+            }
+        }
+    }
+
+    /// Everything sent to and received from the command so far.
+    pub fn transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    /// Close stdin, wait for the command to exit and return its whole output,
+    /// checked like `CommandSpec::run`.
+    pub fn finish(mut self) -> Result<ExecOutput, CommandError> {
+        self.close_stdin();
+        while self.receive(None) {}
+        let result = self
+            .result
+            .take()
+            .expect("the result is set once the command finished");
+        CommandError::check(result?)
+    }
+
+    /// cut the first match of `regex` out of the unread output
+    fn take_match(&mut self, regex: &Regex) -> Option<Match> {
+        let captures = regex.captures(&self.unread)?;
+        let whole = captures.get(0)?;
+        let found = Match {
+            before: text(&self.unread[..whole.start()]),
+            matched: text(whole.as_bytes()),
+            groups: captures
+                .iter()
+                .skip(1)
+                .map(|group| group.map(|g| text(g.as_bytes())))
+                .collect(),
+        };
+        let end = whole.end();
+        self.unread.drain(..end);
+        Some(found)
+    }
+
+    /// wait for more output until `deadline`, `false` when none came and none will
+    fn receive(&mut self, deadline: Option<Instant>) -> bool {
+        let Some(running) = self.running.as_mut() else {
+            return false;
+        };
+        match running.poll_chunk(deadline) {
+            Ok(Polled::Chunk(pipe, bytes)) => {
+                if pipe == Pipe::Stdout || self.match_stderr {
+                    self.unread.extend_from_slice(&bytes);
+                }
+                self.transcript.push(Direction::Received, &bytes);
+                true
+            }
+            Ok(Polled::Expired) => false,
+            Ok(Polled::Finished) => {
+                self.result = self.running.take().map(Running::finish);
+                false
+            }
+            Err(err) => {
+                self.running = None;
+                self.result = Some(Err(err));
+                false
+            }
+        }
+    }
+}
+
+fn text(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// Which way bytes went in a `Transcript`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// written to the stdin of the command
+    Sent,
+    /// printed by the command, on stdout or stderr
+    Received,
+}
+
+/// # Transcript
+/// The conversation with an interactive command, in order.
+///
+/// Displayed as the command line followed by every line, prefixed with `> `
+/// when it was sent and `< ` when it was received.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    command: String,
+    entries: Vec<(Direction, Vec<u8>)>,
+}
+
+impl Transcript {
+    /// An empty transcript of `command`.
+    pub fn new(command: impl Into<String>) -> Self {
+        Transcript {
+            command: command.into(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// The command line of the command.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// What was sent and received, consecutive pieces going the same way are joined.
+    pub fn entries(&self) -> &[(Direction, Vec<u8>)] {
+        &self.entries
+    }
+
+    /// Add bytes that went `direction`.
+    pub fn push(&mut self, direction: Direction, bytes: &[u8]) {
+        match self.entries.last_mut() {
+            Some((last, joined)) if *last == direction => joined.extend_from_slice(bytes),
+            _ => self.entries.push((direction, bytes.to_vec())),
+        }
+    }
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "$ {}", self.command)?;
+        for (direction, bytes) in &self.entries {
+            let prefix = match direction {
+                Direction::Sent => "> ",
+                Direction::Received => "< ",
+            };
+            for line in String::from_utf8_lossy(bytes).lines() {
+                writeln!(f, "{}{}", prefix, line)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,21 @@
+#[cfg(feature = "async")]
+pub mod async_execute;
+pub mod batch;
+pub mod cassette;
 pub mod execute;
 pub mod filestore;
+pub mod interactive;
 pub mod color;
+pub mod config;
+pub mod error;
+pub mod output;
+pub mod pipeline;
+mod process;
+pub mod pty;
+pub mod retry;
+pub mod runner;
+pub mod shell;
+pub mod shellwords;
+pub mod tasks;
+#[cfg(test)]
+mod tests;
@@ -1,12 +1,600 @@
-#![allow(unused_imports)]
-use commandcrafter::{execute::Execute, filestore::Filestore};
+use commandcrafter::color::{self, Col};
+use commandcrafter::config::{parse_duration, ConfigError, CrafterConfig, TimeoutValue};
+use commandcrafter::execute::{self, CommandError, CommandSpec, ExecOutput, OutputEvent};
+use commandcrafter::filestore::Filestore;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Component, Path};
+use std::process::exit;
+use std::time::Duration;
+
+const USAGE: &str = "usage: commandcrafter [options] <command>
+
+commands:
+  run <task|cmd> [args...]   run a task of the task file, or any command
+  batch <file>               run the command lines of a file in parallel
+  logs list                  list the stored logs
+  logs show <name>           print a log
+  logs tail <name> [-n N]    print the last lines of a log (10 by default)
+  logs clean                 delete every stored log
+  config                     show the task file and its tasks
+  list                       list the names of the tasks
+
+options:
+  --log <file>       also write the output into <file> in the log folder,
+                     {command}, {pid}, {date} and {time} are filled in
+  --overwrite        replace the log when it exists instead of numbering a new one
+  --timeout <time>   stop the commands after <time> (500ms, 30s, 5m, 1h)
+  --json             print the results as JSON
+  --no-color         don't color the output (also when $NO_COLOR is set)
+  --quiet            print nothing, only set the exit code
+  --dry-run          print what would be executed and written instead of doing it
+  -h, --help         print this help
+
+Tasks are read from Crafter.toml in the current directory,
+or from the file named by $COMMANDCRAFTER_CONFIG.
+Logs are stored in $COMMANDCRAFTER_LOG_DIR, or $XDG_STATE_HOME/commandcrafter/logs
+(~/.local/state/commandcrafter/logs when it isn't set).
+The exit code is the one of the command (128 + signal when it was killed,
+124 on timeout, 126 when it can't be executed and 127 when it isn't found).";
+
+/// exit code for a command line commandcrafter doesn't understand
+const USAGE_ERROR: i32 = 2;
+
+/// the flags given on the command line
+#[derive(Debug, Default)]
+struct Options {
+    log: Option<String>,
+    overwrite: bool,
+    timeout: Option<String>,
+    json: bool,
+    quiet: bool,
+    no_color: bool,
+    dry_run: bool,
+    lines: Option<usize>,
+}
 
 fn main() {
-    match Execute::exe("lk", &["--all"]) {
-        Ok(_) => println!("Command executed successfully."),
-        Err(e) => eprintln!("Error executing command: {}", e),
+    let (opts, words) = match parse_args(std::env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(message) => usage_error(&message),
+    };
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    if opts.no_color || opts.json || no_color_env || !io::stdout().is_terminal() {
+        color::set_enabled(false);
+    }
+    if opts.dry_run {
+        execute::set_dry_run(true);
+    }
+
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    let code = match words.as_slice() {
+        ["run", target, args @ ..] => run(&opts, target, args),
+        ["batch", file] => batch(&opts, file),
+        ["logs"] | ["logs", "list"] => logs_list(&opts),
+        ["logs", "show", name] => logs_show(&opts, name),
+        ["logs", "tail", name] => logs_tail(&opts, name, opts.lines.unwrap_or(10)),
+        ["logs", "clean"] => logs_clean(&opts),
+        ["config"] => show_config(&opts),
+        ["list"] => list(&opts),
+        ["help"] => {
+            println!("{}", USAGE);
+            0
+        }
+        [] => usage_error("no command given"),
+        _ => usage_error(&format!("unknown command '{}'", words.join(" "))),
+    };
+    exit(code)
+}
+
+/// split the arguments into options and words, everything after the command of `run` is kept as is
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(Options, Vec<String>), String> {
+    let mut opts = Options::default();
+    let mut words = Vec::new();
+    while let Some(arg) = args.next() {
+        let verbatim = words.first().is_some_and(|w| w == "run") && words.len() >= 2;
+        if verbatim || !arg.starts_with('-') || arg == "-" {
+            words.push(arg);
+            continue;
+        }
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg.clone(), None),
+        };
+        let mut value = |name: &str| {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} needs a value", name))
+        };
+        match flag.as_str() {
+            "--" => words.extend(args.by_ref()),
+            "--log" => opts.log = Some(value("--log")?),
+            "--timeout" => {
+                let timeout = value("--timeout")?;
+                parse_duration(&timeout)?;
+                opts.timeout = Some(timeout);
+            }
+            "-n" | "--lines" => {
+                let lines = value(&flag)?;
+                opts.lines = Some(
+                    lines
+                        .parse()
+                        .map_err(|_| format!("'{}' is not a number of lines", lines))?,
+                );
+            }
+            "--overwrite" => opts.overwrite = true,
+            "--json" => opts.json = true,
+            "--quiet" | "-q" => opts.quiet = true,
+            "--no-color" => opts.no_color = true,
+            "--dry-run" => opts.dry_run = true,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                exit(0)
+            }
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+    Ok((opts, words))
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", Col::Red.print_col(message), USAGE);
+    exit(USAGE_ERROR)
+}
+
+/// the task file, an empty one when it doesn't exist
+fn load_config() -> Result<CrafterConfig, ConfigError> {
+    match CrafterConfig::load(CrafterConfig::default_path()) {
+        Err(ConfigError::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound => {
+            Ok(CrafterConfig::default())
+        }
+        loaded => loaded,
+    }
+}
+
+/// print an error of commandcrafter itself (not of the command) and return the exit code for it
+fn fail(opts: &Options, message: &str) -> i32 {
+    if opts.json {
+        println!("{}", json!({ "error": message }));
+    } else if !opts.quiet {
+        eprintln!("{}", Col::Red.print_col(message));
+    }
+    1
+}
+
+/// the exit code mirroring how the command ended, the same a shell would give
+fn exit_code(result: &Result<ExecOutput, CommandError>) -> i32 {
+    match result {
+        Ok(_) => 0,
+        Err(CommandError::NonZeroExit { code, .. }) => *code,
+        Err(CommandError::Signaled { signal, .. }) => 128 + signal,
+        Err(CommandError::Timeout { .. }) => 124,
+        Err(CommandError::Cancelled { .. }) => 130,
+        Err(CommandError::PermissionDenied { .. }) => 126,
+        Err(CommandError::NotFound { .. }) => 127,
+        Err(_) => 1,
+    }
+}
+
+/// the exit code of the first failure, skipped commands only count when nothing else failed
+fn first_failure<'a>(results: impl Iterator<Item = &'a Result<ExecOutput, CommandError>>) -> i32 {
+    let mut code = 0;
+    for result in results {
+        match result {
+            Ok(_) => {}
+            Err(CommandError::Skipped { .. }) => code = 1,
+            Err(_) => return exit_code(result),
+        }
+    }
+    code
+}
+
+/// a result as a JSON object
+fn to_json(result: &Result<ExecOutput, CommandError>) -> Value {
+    let (output, error) = match result {
+        Ok(output) => (Some(output), None),
+        Err(e) => (e.output(), Some(e.to_string())),
+    };
+    let mut value = json!({
+        "success": result.is_ok(),
+        "exit_code": exit_code(result),
+        "error": error,
+    });
+    if let Some(out) = output {
+        value["command"] = json!(out.command);
+        value["pid"] = json!(out.pid);
+        value["code"] = json!(out.code());
+        value["signal"] = json!(out.signal);
+        value["duration_ms"] = json!(out.duration.as_secs_f64() * 1000.0);
+        value["stdout"] = json!(out.stdout_str());
+        value["stderr"] = json!(out.stderr_str());
+        value["attempts"] = json!(out.attempts.len() + 1);
+    }
+    value
+}
+
+/// write the results into the file of `--log`
+fn write_log(opts: &Options, results: &[Result<ExecOutput, CommandError>]) {
+    if let Some(log) = &opts.log {
+        if let Err(e) = Filestore::default()
+            .overwrite(opts.overwrite)
+            .write_log(results, log)
+        {
+            fail(opts, &format!("couldn't write the log '{}': {}", log, e));
+        }
+    }
+}
+
+/// run a task of the task file when `target` names one, the command `target args...` otherwise
+fn run(opts: &Options, target: &str, args: &[&str]) -> i32 {
+    // only a bare name can be a task
+    if args.is_empty() {
+        match load_config() {
+            Ok(mut config) if config.tasks.contains_key(target) => {
+                if let Some(timeout) = &opts.timeout {
+                    for task in config.tasks.values_mut() {
+                        task.timeout = Some(TimeoutValue::Text(timeout.clone()));
+                    }
+                }
+                return run_task(opts, &config, target);
+            }
+            Ok(_) => {}
+            // a broken task file doesn't stop a program from running
+            Err(e) if is_program(target) => {
+                if !opts.quiet && !opts.json {
+                    eprintln!(
+                        "{}",
+                        Col::Yellow.print_col(&format!("ignoring the task file: {}", e))
+                    );
+                }
+            }
+            Err(e) => return fail(opts, &e.to_string()),
+        }
+    }
+
+    let mut spec = CommandSpec::new(target).args(args.iter().copied());
+    if let Some(timeout) = opts.timeout.as_deref().and_then(|t| parse_duration(t).ok()) {
+        spec = spec.timeout(timeout);
+    }
+    let result = if opts.json || opts.quiet {
+        spec.run()
+    } else {
+        spec.stream(|event| match event {
+            OutputEvent::Stdout(line) => println!("{}", line),
+            OutputEvent::Stderr(line) => eprintln!("{}", line),
+            OutputEvent::Exit(_) => {}
+        })
+    };
+    write_log(opts, std::slice::from_ref(&result));
+    if opts.json {
+        println!("{}", to_json(&result));
+    } else if !opts.quiet {
+        match &result {
+            // the command already printed its stderr
+            Err(CommandError::NonZeroExit { code, .. }) => {
+                eprintln!("{}", Col::Red.print_col(&format!("exit code {}", code)))
+            }
+            Err(e) => eprintln!("{}", Col::Red.print_col(&e.to_string())),
+            Ok(_) => {}
+        }
+    }
+    exit_code(&result)
+}
+
+/// `true` when `name` is the path of a file or a program found in `$PATH`
+fn is_program(name: &str) -> bool {
+    if name.contains(std::path::MAIN_SEPARATOR) || name.contains('/') {
+        return Path::new(name).is_file();
+    }
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(name).is_file()))
+}
+
+/// run a task and everything it depends on, then write the logs of the tasks that have one
+fn run_task(opts: &Options, config: &CrafterConfig, task: &str) -> i32 {
+    let report = match config.graph(task).and_then(|graph| Ok(graph.run()?)) {
+        Ok(report) => report,
+        Err(e) => return fail(opts, &e.to_string()),
+    };
+    for (name, result) in &report.results {
+        let Some(task) = config.tasks.get(name) else {
+            continue;
+        };
+        if let Some(log) = &task.log {
+            if let Err(e) = task
+                .filestore()
+                .overwrite(opts.overwrite)
+                .write_log(std::slice::from_ref(result), log)
+            {
+                fail(
+                    opts,
+                    &format!("couldn't write the log of '{}': {}", name, e),
+                );
+            }
+        }
+    }
+    let code = first_failure(report.results.iter().map(|(_, result)| result));
+    if opts.json {
+        let tasks: Vec<Value> = report
+            .results
+            .iter()
+            .map(|(name, result)| {
+                let mut value = to_json(result);
+                value["task"] = json!(name);
+                value
+            })
+            .collect();
+        println!("{}", Value::Array(tasks));
+    } else if !opts.quiet {
+        report.print_summary();
+    }
+    let outputs: Vec<_> = report
+        .results
+        .into_iter()
+        .map(|(_, result)| result)
+        .collect();
+    write_log(opts, &outputs);
+    code
+}
+
+/// run every command line of `file` in parallel, lines starting with `#` are ignored
+fn batch(opts: &Options, file: &str) -> i32 {
+    let content = match fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) => return fail(opts, &format!("couldn't read '{}': {}", file, e)),
+    };
+    let timeout = opts.timeout.as_deref().and_then(|t| parse_duration(t).ok());
+    let mut batch = commandcrafter::batch::Batch::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match CommandSpec::parse(line) {
+            Ok(spec) => batch = batch.push(with_timeout(spec, timeout)),
+            Err(e) => return fail(opts, &format!("{}:{}: {}", file, number + 1, e)),
+        }
+    }
+    let out = batch.run();
+    write_log(opts, &out.results);
+    if opts.json {
+        let results: Vec<Value> = out.results.iter().map(to_json).collect();
+        println!("{}", Value::Array(results));
+    } else if !opts.quiet {
+        for result in &out.results {
+            print_result(result);
+        }
+        println!(
+            "{} succeeded, {} failed in {:.2?}",
+            out.succeeded(),
+            out.failed(),
+            out.elapsed
+        );
+    }
+    first_failure(out.results.iter())
+}
+
+fn with_timeout(spec: CommandSpec, timeout: Option<Duration>) -> CommandSpec {
+    match timeout {
+        Some(timeout) => spec.timeout(timeout),
+        None => spec,
+    }
+}
+
+/// print the command line, the status and what a command of a batch printed
+fn print_result(result: &Result<ExecOutput, CommandError>) {
+    match result {
+        Ok(out) => println!("{} {}", Col::Green.print_col("ok"), out.command),
+        Err(e) => match e.output() {
+            Some(out) => println!("{} {}: {}", Col::Red.print_col("failed"), out.command, e),
+            None => println!("{} {}", Col::Red.print_col("failed"), e),
+        },
+    }
+    if let Some(out) = result
+        .as_ref()
+        .ok()
+        .or_else(|| result.as_ref().err()?.output())
+    {
+        print!("{}", out.stdout_str());
+        eprint!("{}", out.stderr_str());
+    }
+}
+
+/// the log named `name` inside the log folder, names reaching out of it are refused
+fn log_path(name: &str) -> Result<std::path::PathBuf, String> {
+    let inside = Path::new(name)
+        .components()
+        .all(|part| matches!(part, Component::Normal(_) | Component::CurDir));
+    if !inside {
+        return Err(format!("'{}' is not a log inside the log folder", name));
+    }
+    Ok(Filestore::default().path(name))
+}
+
+/// the names and sizes of the stored logs, sorted by name, the ones in
+/// subfolders named like `2024-05-31/make-14-03-59-4242.log`
+fn log_entries() -> Result<Vec<(String, u64)>, CommandError> {
+    let mut entries = Vec::new();
+    let mut folders = vec![String::new()];
+    while let Some(folder) = folders.pop() {
+        let read = match fs::read_dir(Filestore::default().path(&folder)) {
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for entry in read {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let name = if folder.is_empty() {
+                name
+            } else {
+                format!("{}/{}", folder, name)
+            };
+            if meta.is_dir() {
+                folders.push(name);
+            } else if meta.is_file() {
+                entries.push((name, meta.len()));
+            }
+        }
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+fn logs_list(opts: &Options) -> i32 {
+    let entries = match log_entries() {
+        Ok(entries) => entries,
+        Err(e) => return fail(opts, &e.to_string()),
+    };
+    if opts.json {
+        let logs: Vec<Value> = entries
+            .iter()
+            .map(|(name, size)| json!({ "name": name, "size": size }))
+            .collect();
+        println!("{}", Value::Array(logs));
+    } else if !opts.quiet {
+        for (name, size) in entries {
+            println!("{:>10}  {}", size, Col::Cyan.print_col(&name));
+        }
+    }
+    0
+}
+
+fn logs_show(opts: &Options, name: &str) -> i32 {
+    let path = match log_path(name) {
+        Ok(path) => path,
+        Err(message) => return fail(opts, &message),
+    };
+    match fs::read_to_string(path) {
+        Ok(content) => {
+            if opts.json {
+                println!("{}", json!({ "name": name, "content": content }));
+            } else if !opts.quiet {
+                print!("{}", content);
+            }
+            0
+        }
+        Err(e) => fail(opts, &format!("couldn't read '{}': {}", name, e)),
     }
+}
 
-    let _ = Execute::exe("pacman", &["-Qu", "--color=always"]);
-    let _ = Execute::exe("yay", &["-Qu", "--color=always"]);
+fn logs_tail(opts: &Options, name: &str, lines: usize) -> i32 {
+    let path = match log_path(name) {
+        Ok(path) => path,
+        Err(message) => return fail(opts, &message),
+    };
+    match fs::read_to_string(path) {
+        Ok(content) => {
+            let all: Vec<&str> = content.lines().collect();
+            let last = &all[all.len().saturating_sub(lines)..];
+            if opts.json {
+                println!("{}", json!({ "name": name, "lines": last }));
+            } else if !opts.quiet {
+                for line in last {
+                    println!("{}", line);
+                }
+            }
+            0
+        }
+        Err(e) => fail(opts, &format!("couldn't read '{}': {}", name, e)),
+    }
+}
+
+fn logs_clean(opts: &Options) -> i32 {
+    let removed = log_entries().and_then(|entries| {
+        for (name, _) in &entries {
+            let path = Filestore::default().path(name);
+            if opts.dry_run {
+                println!(
+                    "{} would remove the file {}",
+                    Col::Yellow.print_col("[dry-run]"),
+                    path.display()
+                );
+            } else {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(entries.len())
+    });
+    match removed {
+        Ok(count) => {
+            if opts.json {
+                println!("{}", json!({ "removed": count }));
+            } else if !opts.quiet {
+                println!("{} {} logs", Col::Green.print_col("removed"), count);
+            }
+            0
+        }
+        Err(e) => fail(opts, &e.to_string()),
+    }
+}
+
+/// print where the task file is and what its tasks run
+fn show_config(opts: &Options) -> i32 {
+    let path = CrafterConfig::default_path();
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(e) => return fail(opts, &e.to_string()),
+    };
+    let log_dir = Filestore::log_dir();
+    if opts.json {
+        let value = json!({
+            "path": path,
+            "found": path.exists(),
+            "log_dir": log_dir,
+            "tasks": config.tasks,
+        });
+        println!("{}", value);
+        return 0;
+    }
+    if opts.quiet {
+        return 0;
+    }
+    if !path.exists() {
+        println!("task file: {} (not found)", path.display());
+    } else {
+        println!("task file: {}", path.display());
+    }
+    println!("log folder: {}", log_dir.display());
+    for (name, task) in &config.tasks {
+        let line = match task.to_spec(name) {
+            Ok(spec) => spec.command_line(),
+            Err(e) => Col::Red.print_col(&e.to_string()),
+        };
+        println!("{}  {}", Col::Cyan.print_col(name), line);
+        if !task.depends_on.is_empty() {
+            println!("    after {}", task.depends_on.join(", "));
+        }
+    }
+    0
+}
+
+/// print the name and description of every task
+fn list(opts: &Options) -> i32 {
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(e) => return fail(opts, &e.to_string()),
+    };
+    if opts.json {
+        let tasks: Vec<Value> = config
+            .tasks
+            .iter()
+            .map(|(name, task)| json!({ "name": name, "description": task.description }))
+            .collect();
+        println!("{}", Value::Array(tasks));
+        return 0;
+    }
+    if opts.quiet {
+        return 0;
+    }
+    for (name, task) in &config.tasks {
+        match &task.description {
+            Some(description) => println!("{}  {}", Col::Cyan.print_col(name), description),
+            None => println!("{}", Col::Cyan.print_col(name)),
+        }
+    }
+    0
 }
@@ -0,0 +1,154 @@
+//! # Output module
+//! Everything we know about a finished command: what it printed on
+//! stdout and stderr, how it exited, how long it took and what was run.
+
+use crate::retry::Attempt;
+use std::borrow::Cow;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// The result of a command that ran until the end.
+///
+/// Unlike `std::process::Output` it keeps the pid, the duration and the command
+/// line around, so the value can be printed or logged on its own.
+#[derive(Debug, Clone)]
+pub struct ExecOutput {
+    /// the captured standard output (empty when stdout wasn't piped)
+    pub stdout: Vec<u8>,
+    /// the captured standard error (empty when stderr wasn't piped)
+    pub stderr: Vec<u8>,
+    /// the exit status reported by the OS
+    pub status: ExitStatus,
+    /// the signal that terminated the command, if any (always `None` outside unix)
+    pub signal: Option<i32>,
+    /// the time between spawning the command and reaping it
+    pub duration: Duration,
+    /// the process id the command ran with
+    pub pid: u32,
+    /// the command line that was executed, for display purposes
+    pub command: String,
+    /// the failed attempts before this one when the command was retried (see `RetryPolicy`)
+    pub attempts: Vec<Attempt>,
+}
+
+impl ExecOutput {
+    /// Build the output of a command from its raw parts, the signal is read from `status`.
+    pub fn new(
+        command: String,
+        pid: u32,
+        status: ExitStatus,
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+        duration: Duration,
+    ) -> Self {
+        ExecOutput {
+            stdout,
+            stderr,
+            signal: signal_of(&status),
+            status,
+            duration,
+            pid,
+            command,
+            attempts: Vec::new(),
+        }
+    }
+
+    /// the output of a command that wasn't executed because of the dry-run mode:
+    /// a success with nothing printed, pid 0 and no duration
+    pub(crate) fn dry_run(command: String) -> Self {
+        ExecOutput::new(
+            command,
+            0,
+            ExitStatus::default(),
+            Vec::new(),
+            Vec::new(),
+            Duration::ZERO,
+        )
+    }
+
+    /// `true` when the command exited with a zero status.
+    pub fn success(&self) -> bool {
+        self.status.success()
+    }
+
+    /// The exit code, `None` when the command was killed by a signal.
+    pub fn code(&self) -> Option<i32> {
+        self.status.code()
+    }
+
+    /// The standard output as text, invalid UTF-8 is replaced.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::Execute;
+    ///
+    /// let out = Execute::run("echo", &["hello"]).unwrap();
+    /// assert_eq!(out.stdout_str(), "hello\n");
+    /// assert!(out.success());
+    /// ```
+    pub fn stdout_str(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.stdout)
+    }
+
+    /// The standard error as text, invalid UTF-8 is replaced.
+    pub fn stderr_str(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.stderr)
+    }
+
+    /// The lines of the standard output without their line endings.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::Execute;
+    ///
+    /// let out = Execute::run("printf", &["a\\nb\\r\\nc"]).unwrap();
+    /// let lines: Vec<_> = out.lines().collect();
+    /// assert_eq!(lines, ["a", "b", "c"]);
+    /// ```
+    pub fn lines(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        split_lines(&self.stdout)
+    }
+
+    /// The lines of the standard error without their line endings.
+    pub fn stderr_lines(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        split_lines(&self.stderr)
+    }
+}
+
+/// split `bytes` on `\n`, dropping a trailing `\r` and the empty piece after the last newline
+fn split_lines(bytes: &[u8]) -> impl Iterator<Item = Cow<'_, str>> {
+    let bytes = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+    let empty = bytes.is_empty();
+    bytes
+        .split(|b| *b == b'\n')
+        .filter(move |_| !empty)
+        .map(|line| String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line)))
+}
+
+/// rebuild the exit status of a command that exited with `code` or was killed by `signal`
+#[cfg(unix)]
+pub(crate) fn raw_status(code: Option<i32>, signal: Option<i32>) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    match (code, signal) {
+        (_, Some(signal)) => ExitStatus::from_raw(signal & 0x7f),
+        (code, None) => ExitStatus::from_raw((code.unwrap_or(0) & 0xff) << 8),
+    }
+}
+
+/// rebuild the exit status of a command that exited with `code`
+#[cfg(windows)]
+pub(crate) fn raw_status(code: Option<i32>, _signal: Option<i32>) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code.unwrap_or(0) as u32)
+}
+
+#[cfg(unix)]
+fn signal_of(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal_of(_status: &ExitStatus) -> Option<i32> {
+    None
+}
@@ -0,0 +1,204 @@
+//! # Pipeline module
+//! Chain commands like `ps aux | grep foo | wc -l` without invoking a shell:
+//! the stdout of every command is connected to the stdin of the next one with an OS pipe.
+
+use crate::error::CommandError;
+use crate::execute::{print_dry_run, CommandSpec, Stream};
+use crate::output::ExecOutput;
+use crate::process::Running;
+use std::process::ExitStatus;
+use std::time::Instant;
+
+/// # Pipeline
+/// A chain of commands connected stdout to stdin.
+///
+/// Every stage keeps its own settings (cwd, env, stderr...), the stdin of the first
+/// stage and the stdout of the last one are the only ends left to the specs.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::execute::{CommandSpec, Pipeline};
+///
+/// let out = Pipeline::new(CommandSpec::new("printf").arg("b\\na\\nb\\n"))
+///     .pipe(CommandSpec::new("sort"))
+///     .pipe(CommandSpec::new("uniq").arg("-c"))
+///     .run()
+///     .unwrap();
+/// let lines: Vec<_> = out.lines().map(|l| l.trim().to_string()).collect();
+/// assert_eq!(lines, ["1 a", "2 b"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    stages: Vec<CommandSpec>,
+    pipefail: bool,
+}
+
+/// What a pipeline produced: the final output plus the output of every stage.
+#[derive(Debug, Clone)]
+pub struct PipelineOutput {
+    /// the stdout of the last stage, the stderr of every stage and the status of the pipeline
+    pub output: ExecOutput,
+    /// the output of each stage in order, only the last one has a stdout
+    pub stages: Vec<ExecOutput>,
+}
+
+impl PipelineOutput {
+    /// The exit status of every stage, in order.
+    pub fn statuses(&self) -> Vec<ExitStatus> {
+        self.stages.iter().map(|stage| stage.status).collect()
+    }
+}
+
+impl Pipeline {
+    /// Start a pipeline with its first command.
+    pub fn new(first: CommandSpec) -> Self {
+        Pipeline {
+            stages: vec![first],
+            pipefail: false,
+        }
+    }
+
+    /// Append a command reading the stdout of the previous one.
+    pub fn pipe(mut self, next: CommandSpec) -> Self {
+        self.stages.push(next);
+        self
+    }
+
+    /// Like `set -o pipefail`: the status of the pipeline is the one of the last stage
+    /// that failed, instead of the status of the last stage.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::{CommandSpec, Pipeline};
+    ///
+    /// let pipeline = Pipeline::new(CommandSpec::new("false")).pipe(CommandSpec::new("cat"));
+    /// assert!(pipeline.clone().run().is_ok());
+    /// assert!(pipeline.pipefail(true).run().is_err());
+    /// ```
+    pub fn pipefail(mut self, pipefail: bool) -> Self {
+        self.pipefail = pipefail;
+        self
+    }
+
+    /// The pipeline as it would be typed in a shell.
+    pub fn command_line(&self) -> String {
+        self.stages
+            .iter()
+            .map(CommandSpec::command_line)
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+
+    /// Execute every stage and report how each of them exited, whatever their status.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::{CommandSpec, Pipeline};
+    ///
+    /// let res = Pipeline::new(CommandSpec::new("sh").args(["-c", "echo hi; exit 3"]))
+    ///     .pipe(CommandSpec::new("cat"))
+    ///     .output()
+    ///     .unwrap();
+    /// let codes: Vec<_> = res.statuses().iter().map(|s| s.code()).collect();
+    /// assert_eq!(codes, [Some(3), Some(0)]);
+    /// assert_eq!(res.output.stdout_str(), "hi\n");
+    /// ```
+    pub fn output(&self) -> Result<PipelineOutput, CommandError> {
+        if self.stages.iter().any(CommandSpec::get_dry_run) {
+            print_dry_run(&format!("$ {}", self.command_line()));
+            let stages = self
+                .stages
+                .iter()
+                .map(|spec| ExecOutput::dry_run(spec.command_line()))
+                .collect();
+            let output = ExecOutput::dry_run(self.command_line());
+            return Ok(PipelineOutput { output, stages });
+        }
+        let started = Instant::now();
+        let mut running = Vec::with_capacity(self.stages.len());
+        let mut upstream = None;
+        let last = self.stages.len() - 1;
+        for (i, spec) in self.stages.iter().enumerate() {
+            let spawned = if i < last {
+                spec.clone().stdout(Stream::Piped).launch()
+            } else {
+                spec.launch()
+            }
+            .and_then(|mut launch| {
+                if let Some(stdin) = upstream.take() {
+                    launch.cmd.stdin(stdin);
+                    launch.input = None;
+                }
+                if i < last {
+                    Running::spawn_upstream(launch).map(|(run, out)| (run, Some(out)))
+                } else {
+                    Running::spawn(launch).map(|run| (run, None))
+                }
+            });
+            match spawned {
+                Ok((run, out)) => {
+                    running.push(run);
+                    upstream = out;
+                }
+                Err(e) => {
+                    // nothing will read what the stages already started print, stop them
+                    for mut run in running {
+                        run.kill();
+                        let _ = run.finish();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut stages = Vec::with_capacity(running.len());
+        let mut failure = None;
+        for run in running {
+            match run.wait() {
+                Ok(out) => stages.push(out),
+                Err(e) => match e.output() {
+                    // keep the partial output of a stopped stage and report why it stopped
+                    Some(out) => {
+                        stages.push(out.clone());
+                        failure.get_or_insert(e);
+                    }
+                    None => return Err(e),
+                },
+            }
+        }
+        if let Some(e) = failure {
+            return Err(e);
+        }
+
+        let last = stages.last().expect("a pipeline has at least one stage");
+        let status = if self.pipefail {
+            stages
+                .iter()
+                .rev()
+                .find(|stage| !stage.success())
+                .unwrap_or(last)
+                .status
+        } else {
+            last.status
+        };
+        let output = ExecOutput::new(
+            self.command_line(),
+            last.pid,
+            status,
+            last.stdout.clone(),
+            stages
+                .iter()
+                .flat_map(|s| s.stderr.iter().copied())
+                .collect(),
+            started.elapsed(),
+        );
+        Ok(PipelineOutput { output, stages })
+    }
+
+    /// Execute the pipeline and return its final output, in the same shape as `Execute::run`.
+    ///
+    /// Fails when the status of the pipeline isn't a success (see `pipefail`).
+    pub fn run(&self) -> Result<ExecOutput, CommandError> {
+        CommandError::check(self.output()?.output)
+    }
+}
@@ -0,0 +1,705 @@
+//! # Process module
+//! The machinery shared by every way of running a command: spawning the child,
+//! draining its pipes on background threads, feeding its stdin and enforcing
+//! timeouts and cancellation.
+
+use crate::error::CommandError;
+use crate::output::ExecOutput;
+use std::io::{Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// how often a watched command is checked for exit, timeout and cancellation
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A handle that lets another thread stop a running command.
+///
+/// Clones share the same flag, so keep one and hand the other to `CommandSpec::cancel_on`.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::execute::{CancelHandle, CommandError, CommandSpec};
+/// use std::time::Duration;
+///
+/// let handle = CancelHandle::new();
+/// let trigger = handle.clone();
+/// std::thread::spawn(move || {
+///     std::thread::sleep(Duration::from_millis(100));
+///     trigger.cancel();
+/// });
+/// let res = CommandSpec::new("sleep").arg("10").cancel_on(&handle).run();
+/// assert!(matches!(res, Err(CommandError::Cancelled { .. })));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    /// Create a handle that isn't cancelled yet.
+    pub fn new() -> Self {
+        CancelHandle::default()
+    }
+
+    /// Ask every command watching this handle to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// `true` once `cancel` has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// The limits a running command is held to.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Limits {
+    pub timeout: Option<Duration>,
+    pub grace: Duration,
+    /// every handle the command is stopped by, the caller's and a batch's one
+    pub cancel: Vec<CancelHandle>,
+}
+
+impl Limits {
+    /// a command only has to be polled when something may stop it early
+    pub fn is_watched(&self) -> bool {
+        self.timeout.is_some() || !self.cancel.is_empty()
+    }
+}
+
+/// which pipe a chunk of output was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Pipe {
+    Stdout,
+    Stderr,
+}
+
+/// what the reader threads report back
+pub(crate) enum Chunk {
+    Data(Pipe, Vec<u8>),
+    Closed,
+}
+
+/// Where the reader threads send the chunks they read.
+pub(crate) trait ChunkSink: Send + 'static {
+    /// `false` once nobody listens anymore
+    fn send_chunk(&self, chunk: Chunk) -> bool;
+}
+
+impl ChunkSink for Sender<Chunk> {
+    fn send_chunk(&self, chunk: Chunk) -> bool {
+        self.send(chunk).is_ok()
+    }
+}
+
+/// what waiting for output with a deadline got
+pub(crate) enum Polled {
+    /// more output
+    Chunk(Pipe, Vec<u8>),
+    /// the command exited and its pipes are closed
+    Finished,
+    /// the deadline passed first
+    Expired,
+}
+
+/// why a command was stopped before it finished on its own
+#[derive(Debug, Clone, Copy)]
+enum Stop {
+    Timeout(Duration),
+    Cancelled,
+}
+
+/// Holds a command to its limits: asks for SIGTERM once its timeout passed or
+/// it got cancelled, then for SIGKILL when it is still there after the grace period.
+pub(crate) struct Watchdog {
+    limits: Limits,
+    started: Instant,
+    stop: Option<Stop>,
+    kill_at: Option<Instant>,
+}
+
+impl Watchdog {
+    /// watch a command started right now
+    pub fn new(limits: Limits) -> Self {
+        Watchdog {
+            limits,
+            started: Instant::now(),
+            stop: None,
+            kill_at: None,
+        }
+    }
+
+    pub fn is_watched(&self) -> bool {
+        self.limits.is_watched()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// the signal to send now if any, `Some(true)` for SIGKILL and `Some(false)` for SIGTERM
+    pub fn check(&mut self) -> Option<bool> {
+        let now = Instant::now();
+        match (self.stop, self.kill_at) {
+            (None, _) => {
+                let stop = if self.limits.cancel.iter().any(|c| c.is_cancelled()) {
+                    Some(Stop::Cancelled)
+                } else {
+                    self.limits
+                        .timeout
+                        .filter(|t| now.duration_since(self.started) >= *t)
+                        .map(Stop::Timeout)
+                };
+                self.stop = Some(stop?);
+                self.kill_at = Some(now + self.limits.grace);
+                Some(false)
+            }
+            (Some(_), Some(kill_at)) if now >= kill_at => {
+                self.kill_at = None;
+                Some(true)
+            }
+            _ => None,
+        }
+    }
+
+    /// the result of the command that produced `output`, an error when it had to be stopped
+    pub fn verdict(&self, output: ExecOutput) -> Result<ExecOutput, CommandError> {
+        match self.stop {
+            None => Ok(output),
+            Some(Stop::Timeout(after)) => Err(CommandError::Timeout {
+                after,
+                output: Box::new(output),
+            }),
+            Some(Stop::Cancelled) => Err(CommandError::Cancelled {
+                output: Box::new(output),
+            }),
+        }
+    }
+}
+
+/// Signal the whole process group of a command, `kill` picks SIGKILL over SIGTERM.
+#[cfg(unix)]
+pub(crate) fn signal_group(pid: u32, kill: bool) {
+    let sig = if kill { libc::SIGKILL } else { libc::SIGTERM };
+    // SAFETY: kill(2) has no memory safety requirements, the group id is the
+    // pid of a child spawned with `process_group(0)` or in a session of its own
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), sig);
+    }
+}
+
+/// A spawned child whose output is being collected.
+pub(crate) struct Running {
+    child: Child,
+    command: String,
+    watchdog: Watchdog,
+    chunks: Receiver<Chunk>,
+    output: Collected,
+    status: Option<ExitStatus>,
+}
+
+/// The output of a spawned child as its reader threads report it, shared by
+/// `Running` and the async runner.
+pub(crate) struct Collected {
+    open_pipes: usize,
+    /// the master end of the pseudo-terminal of the command, closed once it was reaped
+    terminal: Option<std::fs::File>,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+impl Collected {
+    /// Start draining the pipes of a freshly spawned child into `tx`, `merged` standing
+    /// in for its stdout, and feeding `input` to its stdin.
+    pub fn wire<O, E, I>(
+        stdout: Option<O>,
+        stderr: Option<E>,
+        merged: Option<Merged>,
+        input: Option<(Feed, I)>,
+        tx: impl ChunkSink + Clone,
+    ) -> std::io::Result<Self>
+    where
+        O: Read + Send + 'static,
+        E: Read + Send + 'static,
+        I: Write + Send + 'static,
+    {
+        let mut open_pipes = 0;
+        let mut terminal = None;
+        if let Some(both) = merged {
+            terminal = both.drain(tx.clone())?;
+            open_pipes += 1;
+        } else if let Some(out) = stdout {
+            drain(out, Pipe::Stdout, tx.clone());
+            open_pipes += 1;
+        }
+        if let Some(err) = stderr {
+            drain(err, Pipe::Stderr, tx);
+            open_pipes += 1;
+        }
+        // feed the bytes on their own thread so a chatty child can't deadlock us; it
+        // isn't waited for, a reader that never reaches EOF would keep us there forever
+        if let Some((feed, mut stdin)) = input {
+            thread::spawn(move || {
+                // the child may exit without reading everything, that's fine
+                let _ = match feed {
+                    Feed::Bytes(bytes) => stdin.write_all(&bytes),
+                    Feed::Reader(mut reader) => std::io::copy(&mut reader, &mut stdin).map(drop),
+                };
+            });
+        }
+        Ok(Collected {
+            open_pipes,
+            terminal,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        })
+    }
+
+    /// `true` once every pipe reached EOF
+    pub fn is_closed(&self) -> bool {
+        self.open_pipes == 0
+    }
+
+    /// keep what a reader thread reported, handing the bytes back when it read some
+    pub fn record(&mut self, chunk: Chunk) -> Option<(Pipe, Vec<u8>)> {
+        match chunk {
+            Chunk::Data(pipe, bytes) => {
+                match pipe {
+                    Pipe::Stdout => self.stdout.extend_from_slice(&bytes),
+                    Pipe::Stderr => self.stderr.extend_from_slice(&bytes),
+                }
+                Some((pipe, bytes))
+            }
+            Chunk::Closed => {
+                self.open_pipes = self.open_pipes.saturating_sub(1);
+                None
+            }
+        }
+    }
+
+    /// The output of the reaped child, an error when `watchdog` had to stop it.
+    pub fn finish(
+        self,
+        command: String,
+        pid: u32,
+        status: ExitStatus,
+        watchdog: &Watchdog,
+    ) -> Result<ExecOutput, CommandError> {
+        // reaped, hanging its terminal up can't kill it anymore
+        drop(self.terminal);
+        let output = ExecOutput::new(
+            command,
+            pid,
+            status,
+            self.stdout,
+            self.stderr,
+            watchdog.elapsed(),
+        );
+        watchdog.verdict(output)
+    }
+}
+
+/// What the feeder thread writes into the stdin of a command.
+pub(crate) enum Feed {
+    Bytes(Vec<u8>),
+    Reader(Box<dyn Read + Send>),
+}
+
+/// The reading end of something both stdout and stderr of a command write to.
+pub(crate) enum Merged {
+    /// a pipe, like `2>&1`
+    Pipe(std::io::PipeReader),
+    /// the master end of a pseudo-terminal
+    Terminal(std::fs::File),
+}
+
+impl Merged {
+    /// drain it as stdout, returning what must stay open until the command is reaped
+    pub fn drain(self, tx: impl ChunkSink) -> std::io::Result<Option<std::fs::File>> {
+        match self {
+            Merged::Pipe(reader) => {
+                drain(reader, Pipe::Stdout, tx);
+                Ok(None)
+            }
+            Merged::Terminal(master) => {
+                // closing the master hangs the terminal up, which would kill a command
+                // still exiting with SIGHUP: the reader may only see EIO first
+                let keep = master.try_clone()?;
+                drain(master, Pipe::Stdout, tx);
+                Ok(Some(keep))
+            }
+        }
+    }
+}
+
+impl From<Merged> for Stdio {
+    fn from(merged: Merged) -> Self {
+        match merged {
+            Merged::Pipe(reader) => reader.into(),
+            Merged::Terminal(master) => master.into(),
+        }
+    }
+}
+
+impl Launch {
+    /// Put a watched command in a process group of its own and spawn it with `spawn`,
+    /// which takes `cmd`: our copies of the writing ends of the pipes live in it, they
+    /// must go for the pipes to reach EOF.
+    pub fn spawn_with<C>(
+        mut self,
+        spawn: impl FnOnce(Command) -> std::io::Result<C>,
+    ) -> Result<(C, Spawned), CommandError> {
+        self.isolate();
+        let Launch {
+            cmd,
+            program,
+            command,
+            input,
+            merged,
+            limits,
+        } = self;
+        let watchdog = Watchdog::new(limits);
+        let child = spawn(cmd).map_err(|e| CommandError::spawn(&program, e))?;
+        let spawned = Spawned {
+            command,
+            input,
+            merged,
+            watchdog,
+        };
+        Ok((child, spawned))
+    }
+
+    /// Put a watched command in a process group of its own, so stopping it takes
+    /// down everything it started.
+    fn isolate(&mut self) {
+        // a command behind a terminal already gets a session, hence a group, of its own
+        #[cfg(unix)]
+        if self.limits.is_watched() && !matches!(self.merged, Some(Merged::Terminal(_))) {
+            use std::os::unix::process::CommandExt;
+            self.cmd.process_group(0);
+        }
+    }
+}
+
+/// Everything needed to start a command, as prepared by `CommandSpec`.
+pub(crate) struct Launch {
+    pub cmd: Command,
+    /// only used to classify spawn errors
+    pub program: String,
+    /// the line shown in the output
+    pub command: String,
+    /// what to feed to stdin, which must be piped
+    pub input: Option<Feed>,
+    /// where both stdout and stderr end up, it stands in for stdout
+    pub merged: Option<Merged>,
+    pub limits: Limits,
+}
+
+/// What is left of a `Launch` once its command was spawned.
+pub(crate) struct Spawned {
+    pub command: String,
+    pub input: Option<Feed>,
+    pub merged: Option<Merged>,
+    pub watchdog: Watchdog,
+}
+
+impl Running {
+    /// Spawn the command, start draining whatever pipes it has and feed its stdin.
+    pub fn spawn(launch: Launch) -> Result<Self, CommandError> {
+        Ok(Running::start(launch, false)?.0)
+    }
+
+    /// Spawn the command like `spawn`, but hand its stdout over instead of collecting it,
+    /// so it can become the stdin of the next command in a pipeline.
+    pub fn spawn_upstream(launch: Launch) -> Result<(Self, Stdio), CommandError> {
+        let (running, stdout) = Running::start(launch, true)?;
+        Ok((running, stdout.unwrap_or_else(Stdio::null)))
+    }
+
+    fn start(launch: Launch, hand_off: bool) -> Result<(Self, Option<Stdio>), CommandError> {
+        let (mut child, spawned) = launch.spawn_with(|mut cmd| cmd.spawn())?;
+        let Spawned {
+            command,
+            input,
+            mut merged,
+            watchdog,
+        } = spawned;
+
+        let mut stdout = child.stdout.take();
+        let handed_off = if hand_off {
+            match merged.take() {
+                Some(both) => Some(Stdio::from(both)),
+                None => stdout.take().map(Stdio::from),
+            }
+        } else {
+            None
+        };
+        let stdin = input.and_then(|feed| Some((feed, child.stdin.take()?)));
+        let (tx, chunks) = mpsc::channel();
+        let output = Collected::wire(stdout, child.stderr.take(), merged, stdin, tx)?;
+
+        let running = Running {
+            child,
+            command,
+            watchdog,
+            chunks,
+            output,
+            status: None,
+        };
+        Ok((running, handed_off))
+    }
+
+    /// Wait for the command to finish and collect its output.
+    ///
+    /// A command stopped by its timeout or cancel handle is reported as an error
+    /// carrying whatever output was collected until then.
+    pub fn wait(mut self) -> Result<ExecOutput, CommandError> {
+        while self.next_chunk()?.is_some() {}
+        self.finish()
+    }
+
+    /// Block until the command produces more output, `None` once it exited and its pipes are closed.
+    ///
+    /// The chunk is also kept for the final `ExecOutput`.
+    pub fn next_chunk(&mut self) -> Result<Option<(Pipe, Vec<u8>)>, CommandError> {
+        match self.poll_chunk(None)? {
+            Polled::Chunk(pipe, bytes) => Ok(Some((pipe, bytes))),
+            Polled::Finished => Ok(None),
+            Polled::Expired => unreachable!("there is no deadline to expire"),
+        }
+    }
+
+    /// Like `next_chunk`, but give up with `Polled::Expired` once `deadline` passed.
+    pub fn poll_chunk(&mut self, deadline: Option<Instant>) -> Result<Polled, CommandError> {
+        // only poll when something may have to stop the command early, or us waiting
+        let limited = self.watchdog.is_watched();
+        let watched = limited || deadline.is_some();
+        loop {
+            if watched && self.status.is_none() {
+                self.status = self.child.try_wait()?;
+            }
+            if self.status.is_some() && self.output.is_closed() {
+                return Ok(Polled::Finished);
+            }
+            if limited {
+                self.enforce_limits();
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(Polled::Expired);
+            }
+            let chunk = match (self.output.is_closed(), watched) {
+                (true, true) => {
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+                (true, false) => {
+                    self.status = Some(self.child.wait()?);
+                    continue;
+                }
+                (false, true) => match self.chunks.recv_timeout(POLL_INTERVAL) {
+                    Ok(chunk) => chunk,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => Chunk::Closed,
+                },
+                (false, false) => self.chunks.recv().unwrap_or(Chunk::Closed),
+            };
+            if let Some((pipe, bytes)) = self.output.record(chunk) {
+                return Ok(Polled::Chunk(pipe, bytes));
+            }
+        }
+    }
+
+    /// send SIGTERM once the deadline passed or we got cancelled, then SIGKILL after the grace period
+    fn enforce_limits(&mut self) {
+        if let Some(kill) = self.watchdog.check() {
+            self.signal(kill);
+        }
+    }
+
+    #[cfg(unix)]
+    fn signal(&mut self, kill: bool) {
+        signal_group(self.child.id(), kill);
+    }
+
+    #[cfg(not(unix))]
+    fn signal(&mut self, _kill: bool) {
+        let _ = self.child.kill();
+    }
+
+    /// Kill the command right away, `finish` then reaps it.
+    pub fn kill(&mut self) {
+        let _ = self.child.kill();
+    }
+
+    /// the writing end of stdin, when it is piped and nothing is fed to it
+    pub fn take_stdin(&mut self) -> Option<std::process::ChildStdin> {
+        self.child.stdin.take()
+    }
+
+    /// the exit status, once the command finished
+    pub fn status(&self) -> Option<ExitStatus> {
+        self.status
+    }
+
+    /// Build the final output, reaping the child if that didn't happen yet.
+    pub fn finish(mut self) -> Result<ExecOutput, CommandError> {
+        let status = match self.status {
+            Some(status) => status,
+            None => self.child.wait()?,
+        };
+        let pid = self.child.id();
+        self.output
+            .finish(self.command, pid, status, &self.watchdog)
+    }
+}
+
+/// read `pipe` on its own thread until EOF, forwarding every chunk
+pub(crate) fn drain<R: Read + Send + 'static>(mut pipe: R, which: Pipe, tx: impl ChunkSink) {
+    thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        loop {
+            match pipe.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if !tx.send_chunk(Chunk::Data(which, buf[..n].to_vec())) {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+        tx.send_chunk(Chunk::Closed);
+    });
+}
+
+/// One thing that happened while a command was streaming.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputEvent {
+    /// a line written on stdout, without its line ending
+    Stdout(String),
+    /// a line written on stderr, without its line ending
+    Stderr(String),
+    /// the command exited, this is always the last event; it is missing only when
+    /// reading the output failed before the exit status was known
+    Exit(ExitStatus),
+}
+
+/// An iterator over the output of a running command, line by line, as it happens.
+///
+/// The full output is still accumulated, `finish` returns it once the iterator is done.
+/// Created by `CommandSpec::events`.
+pub struct OutputEvents {
+    running: Option<Running>,
+    lines: Lines,
+    pending: std::collections::VecDeque<OutputEvent>,
+    result: Option<Result<ExecOutput, CommandError>>,
+}
+
+impl OutputEvents {
+    pub(crate) fn new(running: Running) -> Self {
+        OutputEvents {
+            running: Some(running),
+            lines: Lines::default(),
+            pending: std::collections::VecDeque::new(),
+            result: None,
+        }
+    }
+
+    /// events of a command that already finished, only its exit is left
+    pub(crate) fn done(output: ExecOutput) -> Self {
+        OutputEvents {
+            running: None,
+            lines: Lines::default(),
+            pending: std::collections::VecDeque::from([OutputEvent::Exit(output.status)]),
+            result: Some(Ok(output)),
+        }
+    }
+
+    /// Consume the remaining events and return the full output of the command.
+    pub fn finish(mut self) -> Result<ExecOutput, CommandError> {
+        for _ in self.by_ref() {}
+        self.result
+            .take()
+            .expect("the result is set once the events are exhausted")
+    }
+
+    /// flush unterminated lines and queue the exit event
+    fn close(&mut self, running: Running) {
+        self.pending.extend(self.lines.flush());
+        if let Some(status) = running.status() {
+            self.pending.push_back(OutputEvent::Exit(status));
+        }
+        self.result = Some(running.finish());
+    }
+}
+
+impl Iterator for OutputEvents {
+    type Item = OutputEvent;
+
+    fn next(&mut self) -> Option<OutputEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+            let running = self.running.as_mut()?;
+            match running.next_chunk() {
+                Ok(Some((pipe, bytes))) => self.pending.extend(self.lines.split(pipe, &bytes)),
+                Ok(None) => {
+                    let running = self.running.take()?;
+                    self.close(running);
+                }
+                Err(err) => {
+                    // still report the lines read so far, and the exit when we saw it
+                    self.pending.extend(self.lines.flush());
+                    if let Some(status) = self.running.take().and_then(|r| r.status()) {
+                        self.pending.push_back(OutputEvent::Exit(status));
+                    }
+                    self.result = Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+/// Cuts the output of a command into line events as it comes, chunk by chunk.
+#[derive(Default)]
+pub(crate) struct Lines {
+    partial: [Vec<u8>; 2],
+}
+
+impl Lines {
+    /// the events of every line `bytes` completes
+    pub fn split(&mut self, pipe: Pipe, bytes: &[u8]) -> Vec<OutputEvent> {
+        let partial = &mut self.partial[pipe as usize];
+        partial.extend_from_slice(bytes);
+        let mut events = Vec::new();
+        while let Some(pos) = partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = partial.drain(..=pos).collect();
+            events.push(line_event(pipe, &line[..pos]));
+        }
+        events
+    }
+
+    /// the events of the lines left without a line ending, once the command is done
+    pub fn flush(&mut self) -> Vec<OutputEvent> {
+        [Pipe::Stdout, Pipe::Stderr]
+            .into_iter()
+            .map(|pipe| (pipe, std::mem::take(&mut self.partial[pipe as usize])))
+            .filter(|(_, rest)| !rest.is_empty())
+            .map(|(pipe, rest)| line_event(pipe, &rest))
+            .collect()
+    }
+}
+
+fn line_event(pipe: Pipe, line: &[u8]) -> OutputEvent {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let line = String::from_utf8_lossy(line).into_owned();
+    match pipe {
+        Pipe::Stdout => OutputEvent::Stdout(line),
+        Pipe::Stderr => OutputEvent::Stderr(line),
+    }
+}
@@ -0,0 +1,166 @@
+//! # Pty module
+//! Run a command inside a pseudo-terminal instead of pipes. Tools like `ls`,
+//! `git` or `cargo` check whether their output is a terminal and drop their
+//! colors and progress bars when it isn't; behind a pseudo-terminal they print
+//! what an interactive shell would show, and it is still captured.
+
+use std::fs::File;
+use std::io;
+use std::process::Command;
+
+/// The size of the terminal a command sees, 80 columns by 24 rows by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PtySize {
+    /// the number of columns
+    pub cols: u16,
+    /// the number of rows
+    pub rows: u16,
+}
+
+impl PtySize {
+    /// A terminal of `cols` columns by `rows` rows.
+    pub fn new(cols: u16, rows: u16) -> Self {
+        PtySize { cols, rows }
+    }
+}
+
+impl Default for PtySize {
+    fn default() -> Self {
+        PtySize::new(80, 24)
+    }
+}
+
+/// Open a pseudo-terminal of `size` and make it the stdout, stderr and controlling
+/// terminal of `cmd`, returning the master end everything written to it is read from.
+#[cfg(unix)]
+pub(crate) fn attach(cmd: &mut Command, size: PtySize) -> io::Result<File> {
+    use std::os::unix::process::CommandExt;
+
+    let winsize = libc::winsize {
+        ws_row: size.rows,
+        ws_col: size.cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let (master, slave) = open_pair(&winsize)?;
+    cmd.stdout(slave.try_clone()?);
+    cmd.stderr(slave);
+    // SAFETY: setsid(2) and ioctl(2) are async-signal-safe and touch no memory of ours
+    unsafe {
+        cmd.pre_exec(|| {
+            // a session of its own, so the terminal can become its controlling terminal
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            // stdout is the terminal by now; failing leaves only `/dev/tty` unusable
+            libc::ioctl(libc::STDOUT_FILENO, libc::TIOCSCTTY as _, 0);
+            Ok(())
+        });
+    }
+    Ok(master)
+}
+
+/// Open the master and slave ends of a pseudo-terminal of `size`.
+///
+/// Both are close-on-exec from the start: a command spawned by another thread
+/// meanwhile must not inherit the slave, the master would only see the end of
+/// the output once that unrelated command exited too.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn open_pair(size: &libc::winsize) -> io::Result<(File, File)> {
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    // SAFETY: posix_openpt(3) takes no pointers
+    let master = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC) };
+    if master == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the descriptor was just opened and is owned by nobody else
+    let master = unsafe { File::from_raw_fd(master) };
+    let fd = master.as_raw_fd();
+    // SAFETY: grantpt(3) and unlockpt(3) only take the descriptor of the master
+    if unsafe { libc::grantpt(fd) } != 0 || unsafe { libc::unlockpt(fd) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut name: [libc::c_char; 128] = [0; 128];
+    // SAFETY: ptsname_r(3) writes at most `name.len()` bytes, NUL included, into `name`
+    let err = unsafe { libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) };
+    if err != 0 {
+        return Err(io::Error::from_raw_os_error(err));
+    }
+    // SAFETY: `name` holds the NUL-terminated path ptsname_r returned
+    let slave = unsafe {
+        libc::open(
+            name.as_ptr(),
+            libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC,
+        )
+    };
+    if slave == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the descriptor was just opened and is owned by nobody else
+    let slave = unsafe { File::from_raw_fd(slave) };
+    // SAFETY: TIOCSWINSZ reads a `winsize`, which `size` points to
+    if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, size) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((master, slave))
+}
+
+/// Open the master and slave ends of a pseudo-terminal of `size`.
+///
+/// `posix_openpt` doesn't take `O_CLOEXEC` everywhere, the flag is set right after
+/// `openpty` here: a pty command spawned while another thread spawns commands may
+/// see its output end only once those exited.
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn open_pair(size: &libc::winsize) -> io::Result<(File, File)> {
+    use std::os::fd::FromRawFd;
+
+    let (mut master, mut slave) = (-1, -1);
+    // `openpty` takes a mutable pointer on some platforms, it doesn't write through it
+    let mut size = *size;
+    // SAFETY: the pointers are valid for the duration of the call, a null name
+    // and termios are allowed and mean "don't return the name" and "default settings"
+    let res = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            &mut size,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: openpty succeeded, both descriptors are open and owned by nobody else
+    let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+    for file in [&master, &slave] {
+        set_cloexec(file)?;
+    }
+    Ok((master, slave))
+}
+
+/// keep the descriptor out of the commands we spawn, `dup2` clears the flag on stdout and stderr
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn set_cloexec(file: &File) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    // SAFETY: fcntl(2) on a descriptor we own, with no pointer arguments
+    let res = unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC)
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn attach(_cmd: &mut Command, _size: PtySize) -> io::Result<File> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pseudo-terminals are only available on unix",
+    ))
+}
@@ -0,0 +1,340 @@
+//! # Retry module
+//! Run flaky commands (network access, locked package databases...) again when
+//! they fail, waiting a fixed or growing delay between the attempts. Every failed
+//! attempt is kept in `ExecOutput::attempts` so logs show the retry history.
+
+use crate::error::CommandError;
+use crate::output::ExecOutput;
+use crate::process::{CancelHandle, POLL_INTERVAL};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait before the next attempt.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Backoff {
+    /// always the same delay
+    Fixed(Duration),
+    /// `initial`, then multiplied by `factor` after every attempt, never more than `max`
+    Exponential {
+        initial: Duration,
+        factor: f64,
+        max: Duration,
+    },
+}
+
+impl Backoff {
+    /// Wait `delay` between every attempt.
+    pub fn fixed(delay: Duration) -> Self {
+        Backoff::Fixed(delay)
+    }
+
+    /// Wait `initial`, then twice as long after every attempt, up to `max`.
+    pub fn exponential(initial: Duration, max: Duration) -> Self {
+        Backoff::Exponential {
+            initial,
+            factor: 2.0,
+            max,
+        }
+    }
+
+    /// The delay after the failed attempt number `attempt` (starting at 1), before jitter.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::retry::Backoff;
+    /// use std::time::Duration;
+    ///
+    /// let backoff = Backoff::exponential(Duration::from_millis(100), Duration::from_millis(300));
+    /// assert_eq!(backoff.delay(1), Duration::from_millis(100));
+    /// assert_eq!(backoff.delay(2), Duration::from_millis(200));
+    /// assert_eq!(backoff.delay(3), Duration::from_millis(300));
+    ///
+    /// let odd = Backoff::Exponential {
+    ///     initial: Duration::from_secs(1),
+    ///     factor: -2.0,
+    ///     max: Duration::from_secs(5),
+    /// };
+    /// assert_eq!(odd.delay(2), Duration::ZERO);
+    /// ```
+    pub fn delay(&self, attempt: u32) -> Duration {
+        match self {
+            Backoff::Fixed(delay) => *delay,
+            Backoff::Exponential {
+                initial,
+                factor,
+                max,
+            } => {
+                let exponent = attempt.saturating_sub(1).min(64) as i32;
+                let secs = initial.as_secs_f64() * factor.powi(exponent);
+                // a factor below one can take it down to zero, never below
+                if secs.is_finite() {
+                    Duration::from_secs_f64(secs.clamp(0.0, max.as_secs_f64()))
+                } else {
+                    *max
+                }
+            }
+        }
+    }
+}
+
+/// A failed attempt of a command that was tried again.
+#[derive(Debug, Clone)]
+pub struct Attempt {
+    /// what the command produced during this attempt
+    pub output: ExecOutput,
+    /// why the attempt failed, like `exit code 1` or `timed out after 5s`
+    pub reason: String,
+    /// how long we waited before the next attempt
+    pub delay: Duration,
+}
+
+/// # RetryPolicy
+/// When and how often to run a failing command again.
+///
+/// A failure is retried when the command exited with a non-zero code, was killed by
+/// a signal or timed out, and it matches the filters: when exit codes are given the
+/// code has to be one of them (timeouts and signals have none), when stderr patterns
+/// are given stderr has to contain one of them. A command that can't be spawned or
+/// was cancelled is never retried, cancelling it while it waits for its next attempt
+/// ends the wait with `CommandError::Cancelled`.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::execute::CommandSpec;
+/// use commandcrafter::retry::{Backoff, RetryPolicy};
+/// use std::time::Duration;
+///
+/// // fails twice, then succeeds
+/// let marker = std::env::temp_dir().join(format!("retry-doc-{}", std::process::id()));
+/// let _ = std::fs::remove_file(&marker);
+/// let script = r#"n=$(cat "$1" 2>/dev/null || echo 0); echo $((n+1)) > "$1"
+/// [ "$n" -ge 2 ] || { echo "connection reset" >&2; exit 7; }; echo fetched"#;
+/// let out = CommandSpec::new("sh")
+///     .args(["-c", script, "sh", marker.to_str().unwrap()])
+///     .retry(
+///         RetryPolicy::new(5)
+///             .backoff(Backoff::exponential(Duration::from_millis(10), Duration::from_millis(50)))
+///             .jitter(0.5)
+///             .on_exit_codes([7])
+///             .on_stderr("connection reset"),
+///     )
+///     .run()
+///     .unwrap();
+/// assert_eq!(out.stdout_str(), "fetched\n");
+/// assert_eq!(out.attempts.len(), 2);
+/// assert_eq!(out.attempts[0].reason, "exit code 7");
+/// let _ = std::fs::remove_file(&marker);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Backoff,
+    jitter: f64,
+    exit_codes: Vec<i32>,
+    stderr_patterns: Vec<String>,
+}
+
+impl RetryPolicy {
+    /// Run a command at most `max_attempts` times (at least once), waiting
+    /// 200ms after the first failure and twice as long after each next one, up to 30s.
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            backoff: Backoff::exponential(Duration::from_millis(200), Duration::from_secs(30)),
+            jitter: 0.0,
+            exit_codes: Vec::new(),
+            stderr_patterns: Vec::new(),
+        }
+    }
+
+    /// How long to wait between the attempts.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Randomize every delay by up to `fraction` of it in both directions (0.0 to 1.0),
+    /// so commands failing together don't all retry at the same moment.
+    pub fn jitter(mut self, fraction: f64) -> Self {
+        self.jitter = fraction.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Only retry when the command exits with one of `codes`.
+    pub fn on_exit_codes<I: IntoIterator<Item = i32>>(mut self, codes: I) -> Self {
+        self.exit_codes.extend(codes);
+        self
+    }
+
+    /// Only retry when stderr contains `pattern` (or another pattern given the same way).
+    pub fn on_stderr(mut self, pattern: impl Into<String>) -> Self {
+        self.stderr_patterns.push(pattern.into());
+        self
+    }
+
+    /// The most times a command runs.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// `true` when the failure described by `error` should be tried again.
+    pub fn should_retry(&self, error: &CommandError) -> bool {
+        let Some(output) = retryable_output(error) else {
+            return false;
+        };
+        let code_matches = self.exit_codes.is_empty()
+            || error
+                .code()
+                .is_some_and(|code| self.exit_codes.contains(&code));
+        let stderr = output.stderr_str();
+        let stderr_matches = self.stderr_patterns.is_empty()
+            || self
+                .stderr_patterns
+                .iter()
+                .any(|p| stderr.contains(p.as_str()));
+        code_matches && stderr_matches
+    }
+
+    /// The delay to wait after the failed attempt number `attempt`, jitter included.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self.backoff.delay(attempt);
+        if self.jitter == 0.0 {
+            return delay;
+        }
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        delay.mul_f64(1.0 - self.jitter + 2.0 * self.jitter * random)
+    }
+
+    /// Call `attempt` until it succeeds, fails in a way that isn't retried or
+    /// `max_attempts` is reached, sleeping between the calls.
+    ///
+    /// The result is checked like `CommandSpec::run`; the failed attempts are
+    /// recorded in the `attempts` of the output that is returned, successful or not.
+    pub fn run<F>(&self, attempt: F) -> Result<ExecOutput, CommandError>
+    where
+        F: FnMut() -> Result<ExecOutput, CommandError>,
+    {
+        self.run_until(&[], attempt)
+    }
+
+    /// `run`, giving up with `CommandError::Cancelled` as soon as one of `cancel`
+    /// is cancelled while waiting for the next attempt.
+    pub(crate) fn run_until<F>(
+        &self,
+        cancel: &[CancelHandle],
+        mut attempt: F,
+    ) -> Result<ExecOutput, CommandError>
+    where
+        F: FnMut() -> Result<ExecOutput, CommandError>,
+    {
+        let mut attempts = Vec::new();
+        for number in 1.. {
+            match self.next(number, attempt(), &mut attempts) {
+                Next::Done(result) => return result,
+                Next::Retry(delay) => {
+                    let until = Instant::now() + delay;
+                    while !is_cancelled(cancel) && Instant::now() < until {
+                        thread::sleep(
+                            until
+                                .saturating_duration_since(Instant::now())
+                                .min(POLL_INTERVAL),
+                        );
+                    }
+                    if is_cancelled(cancel) {
+                        return Err(cancelled(attempts));
+                    }
+                }
+            }
+        }
+        unreachable!("the loop only ends by returning")
+    }
+
+    /// Check the result of the attempt number `number`, recording it in `attempts`
+    /// when it is going to be tried again.
+    pub(crate) fn next(
+        &self,
+        number: u32,
+        result: Result<ExecOutput, CommandError>,
+        attempts: &mut Vec<Attempt>,
+    ) -> Next {
+        let error = match result.and_then(CommandError::check) {
+            Ok(mut output) => {
+                output.attempts = std::mem::take(attempts);
+                return Next::Done(Ok(output));
+            }
+            Err(error) => error,
+        };
+        if number >= self.max_attempts || !self.should_retry(&error) {
+            return Next::Done(Err(with_attempts(error, std::mem::take(attempts))));
+        }
+        let delay = self.delay(number);
+        if let Some(output) = retryable_output(&error) {
+            attempts.push(Attempt {
+                output: output.clone(),
+                reason: reason(&error),
+                delay,
+            });
+        }
+        Next::Retry(delay)
+    }
+}
+
+/// What follows an attempt.
+pub(crate) enum Next {
+    /// the final result
+    Done(Result<ExecOutput, CommandError>),
+    /// wait this long, then try again
+    Retry(Duration),
+}
+
+/// `true` once one of `cancel` was cancelled
+pub(crate) fn is_cancelled(cancel: &[CancelHandle]) -> bool {
+    cancel.iter().any(CancelHandle::is_cancelled)
+}
+
+/// the error of a command cancelled while waiting to be tried again: the output
+/// of its last attempt, holding the ones before
+pub(crate) fn cancelled(mut attempts: Vec<Attempt>) -> CommandError {
+    let last = attempts
+        .pop()
+        .expect("an attempt is recorded before every retry");
+    let mut output = last.output;
+    output.attempts = attempts;
+    CommandError::Cancelled {
+        output: Box::new(output),
+    }
+}
+
+/// the output of a failure that can be retried at all
+fn retryable_output(error: &CommandError) -> Option<&ExecOutput> {
+    match error {
+        CommandError::NonZeroExit { output, .. }
+        | CommandError::Signaled { output, .. }
+        | CommandError::Timeout { output, .. } => Some(output),
+        _ => None,
+    }
+}
+
+/// a short description of a failure, without the output
+fn reason(error: &CommandError) -> String {
+    match error {
+        CommandError::NonZeroExit { code, .. } => format!("exit code {}", code),
+        CommandError::Signaled { signal, .. } => format!("killed by signal {}", signal),
+        CommandError::Timeout { after, .. } => format!("timed out after {:?}", after),
+        other => other.to_string(),
+    }
+}
+
+/// store the failed attempts in the output held by the final error
+fn with_attempts(mut error: CommandError, attempts: Vec<Attempt>) -> CommandError {
+    match &mut error {
+        CommandError::NonZeroExit { output, .. }
+        | CommandError::Signaled { output, .. }
+        | CommandError::Timeout { output, .. }
+        | CommandError::Cancelled { output } => output.attempts = attempts,
+        _ => {}
+    }
+    error
+}
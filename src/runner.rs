@@ -0,0 +1,266 @@
+//! # Runner module
+//! The `Runner` trait stands between code that needs commands executed and the
+//! processes themselves: `Execute` runs them for real, `MockRunner` answers with
+//! canned outputs so the calling code can be unit-tested without any binary.
+
+use crate::error::CommandError;
+use crate::execute::{CommandSpec, Execute};
+use crate::output::{raw_status, ExecOutput};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Something able to execute a `CommandSpec`.
+///
+/// Write the code that runs commands against `&impl Runner` (or `&dyn Runner`),
+/// pass `Execute` in the program and a `MockRunner` in its tests.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::execute::Execute;
+/// use commandcrafter::runner::Runner;
+///
+/// fn current_branch(runner: &impl Runner) -> String {
+///     let out = runner.run_args("git", &["branch", "--show-current"]);
+///     out.map(|out| out.stdout_str().trim().to_string()).unwrap_or_default()
+/// }
+///
+/// let out = Execute.run_args("echo", &["real"]).unwrap();
+/// assert_eq!(out.stdout_str(), "real\n");
+/// # let _ = current_branch(&Execute);
+/// ```
+pub trait Runner {
+    /// Execute `spec` and return its output whatever its exit status, like `CommandSpec::output`.
+    fn output(&self, spec: &CommandSpec) -> Result<ExecOutput, CommandError>;
+
+    /// Execute `spec` and fail when it doesn't succeed, like `CommandSpec::run`.
+    fn run(&self, spec: &CommandSpec) -> Result<ExecOutput, CommandError> {
+        CommandError::check(self.output(spec)?)
+    }
+
+    /// Execute `program` with `args`, like `Execute::run`.
+    fn run_args(&self, program: &str, args: &[&str]) -> Result<ExecOutput, CommandError> {
+        self.run(&CommandSpec::new(program).args(args.iter().copied()))
+    }
+}
+
+impl Runner for Execute {
+    fn output(&self, spec: &CommandSpec) -> Result<ExecOutput, CommandError> {
+        spec.output()
+    }
+
+    fn run(&self, spec: &CommandSpec) -> Result<ExecOutput, CommandError> {
+        spec.run()
+    }
+}
+
+impl<R: Runner + ?Sized> Runner for &R {
+    fn output(&self, spec: &CommandSpec) -> Result<ExecOutput, CommandError> {
+        (**self).output(spec)
+    }
+
+    fn run(&self, spec: &CommandSpec) -> Result<ExecOutput, CommandError> {
+        (**self).run(spec)
+    }
+}
+
+/// A command a `MockRunner` expects, and what it answers with.
+#[derive(Debug, Clone)]
+pub struct Expectation {
+    program: String,
+    args: Option<Vec<String>>,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    code: i32,
+    not_found: bool,
+    times: Option<usize>,
+    calls: usize,
+}
+
+impl Expectation {
+    /// Expect `program` with any arguments, answering with a success and no output.
+    pub fn new(program: impl Into<String>) -> Self {
+        Expectation {
+            program: program.into(),
+            args: None,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            code: 0,
+            not_found: false,
+            times: None,
+            calls: 0,
+        }
+    }
+
+    /// Only match when the arguments are exactly `args`.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args = Some(args.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Answer with this standard output.
+    pub fn stdout(mut self, stdout: impl Into<Vec<u8>>) -> Self {
+        self.stdout = stdout.into();
+        self
+    }
+
+    /// Answer with this standard error.
+    pub fn stderr(mut self, stderr: impl Into<Vec<u8>>) -> Self {
+        self.stderr = stderr.into();
+        self
+    }
+
+    /// Answer with this exit code (0 by default).
+    pub fn exit_code(mut self, code: i32) -> Self {
+        self.code = code;
+        self
+    }
+
+    /// Answer with `CommandError::NotFound`, as if the program wasn't installed.
+    pub fn not_found(mut self) -> Self {
+        self.not_found = true;
+        self
+    }
+
+    /// Expect exactly `times` calls, by default at least one is expected.
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = Some(times);
+        self
+    }
+
+    fn matches(&self, spec: &CommandSpec) -> bool {
+        self.program == spec.get_program()
+            && self
+                .args
+                .as_deref()
+                .is_none_or(|args| args == spec.get_args())
+            && self.times.is_none_or(|times| self.calls < times)
+    }
+
+    fn is_satisfied(&self) -> bool {
+        match self.times {
+            Some(times) => self.calls == times,
+            None => self.calls > 0,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match &self.args {
+            Some(args) => {
+                crate::shellwords::join(std::iter::once(&self.program).chain(args.iter()))
+            }
+            None => format!("{} <any arguments>", self.program),
+        }
+    }
+}
+
+/// # MockRunner
+/// A `Runner` that never spawns anything: every call is matched against the
+/// expectations, in the order they were added, and answered with their canned output.
+///
+/// A call matching no expectation panics, `verify` panics when an expectation
+/// didn't get the calls it wanted.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::runner::{Expectation, MockRunner, Runner};
+///
+/// let runner = MockRunner::new()
+///     .expect(Expectation::new("git").args(["pull"]).stdout("Already up to date.\n"))
+///     .expect(Expectation::new("cargo").exit_code(101).stderr("error: could not compile\n"));
+///
+/// let pulled = runner.run_args("git", &["pull"]).unwrap();
+/// assert_eq!(pulled.stdout_str(), "Already up to date.\n");
+/// let built = runner.run_args("cargo", &["build"]).unwrap_err();
+/// assert_eq!(built.code(), Some(101));
+///
+/// assert_eq!(runner.calls(), ["git pull", "cargo build"]);
+/// runner.verify();
+/// ```
+#[derive(Debug, Default)]
+pub struct MockRunner {
+    expectations: Mutex<Vec<Expectation>>,
+    calls: Mutex<Vec<CommandSpec>>,
+}
+
+impl MockRunner {
+    /// A runner expecting nothing yet.
+    pub fn new() -> Self {
+        MockRunner::default()
+    }
+
+    /// Add an expectation.
+    pub fn expect(self, expectation: Expectation) -> Self {
+        self.expectations.lock().unwrap().push(expectation);
+        self
+    }
+
+    /// The command lines of every call received so far, in order.
+    pub fn calls(&self) -> Vec<String> {
+        self.specs().iter().map(CommandSpec::command_line).collect()
+    }
+
+    /// The specs of every call received so far, to check their cwd or environment.
+    pub fn specs(&self) -> Vec<CommandSpec> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Panic when an expectation didn't get the number of calls it wanted.
+    ///
+    /// ## Example
+    /// ```rust,should_panic
+    /// use commandcrafter::runner::{Expectation, MockRunner};
+    ///
+    /// // panics: `make install` was never run
+    /// MockRunner::new().expect(Expectation::new("make").args(["install"])).verify();
+    /// ```
+    pub fn verify(&self) {
+        let missing: Vec<String> = self
+            .expectations
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| !e.is_satisfied())
+            .map(|e| match e.times {
+                Some(times) => format!("{} (called {} of {} times)", e.describe(), e.calls, times),
+                None => format!("{} (never called)", e.describe()),
+            })
+            .collect();
+        if !missing.is_empty() {
+            panic!("missing command calls:\n  {}", missing.join("\n  "));
+        }
+    }
+}
+
+impl Runner for MockRunner {
+    fn output(&self, spec: &CommandSpec) -> Result<ExecOutput, CommandError> {
+        self.calls.lock().unwrap().push(spec.clone());
+        let mut expectations = self.expectations.lock().unwrap();
+        let Some(expectation) = expectations.iter_mut().find(|e| e.matches(spec)) else {
+            let expected: Vec<String> = expectations.iter().map(Expectation::describe).collect();
+            drop(expectations);
+            panic!(
+                "unexpected command '{}', expected one of:\n  {}",
+                spec.command_line(),
+                expected.join("\n  ")
+            );
+        };
+        expectation.calls += 1;
+        if expectation.not_found {
+            return Err(CommandError::NotFound {
+                program: spec.get_program().to_string(),
+            });
+        }
+        Ok(ExecOutput::new(
+            spec.command_line(),
+            0,
+            raw_status(Some(expectation.code), None),
+            expectation.stdout.clone(),
+            expectation.stderr.clone(),
+            Duration::ZERO,
+        ))
+    }
+}
@@ -0,0 +1,106 @@
+//! # Shell module
+//! Run a script through a real shell when its features are genuinely wanted:
+//! globs, redirects, `&&`, variables... Values are passed to the script as
+//! positional arguments (`$1`, `$2`...) rather than interpolated into it, so they
+//! never need quoting.
+
+use crate::error::CommandError;
+use crate::execute::CommandSpec;
+use crate::output::ExecOutput;
+use std::env;
+
+/// turns on `set -eu` and `pipefail` when the shell knows it (dash doesn't)
+const STRICT_PRELUDE: &str = "set -eu\n(set -o pipefail) 2>/dev/null && set -o pipefail\n";
+
+/// # Shell
+/// The shell scripts are run through, `sh` by default.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::shell::Shell;
+///
+/// let out = Shell::sh()
+///     .command(r#"for f in "$@"; do echo "<$f>"; done | sort"#)
+///     .args(["b c", "a; rm -rf /"])
+///     .run()
+///     .unwrap();
+/// assert_eq!(out.stdout_str(), "<a; rm -rf />\n<b c>\n");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shell {
+    program: String,
+    strict: bool,
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Shell::sh()
+    }
+}
+
+impl Shell {
+    /// A shell started as `<program> -c <script>`, it has to understand `-c`.
+    pub fn new(program: impl Into<String>) -> Self {
+        Shell {
+            program: program.into(),
+            strict: false,
+        }
+    }
+
+    /// The POSIX shell, `sh -c`.
+    pub fn sh() -> Self {
+        Shell::new("sh")
+    }
+
+    /// Bash, `bash -c`.
+    pub fn bash() -> Self {
+        Shell::new("bash")
+    }
+
+    /// The shell of the user from `$SHELL`, `sh` when it isn't set.
+    pub fn from_env() -> Self {
+        match env::var("SHELL") {
+            Ok(shell) if !shell.is_empty() => Shell::new(shell),
+            _ => Shell::sh(),
+        }
+    }
+
+    /// Stop the script at the first failing command, unset variable or failing
+    /// pipeline stage, like `set -euo pipefail` (pipefail is skipped by shells lacking it).
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::shell::Shell;
+    ///
+    /// assert!(Shell::sh().run("false; echo still running").is_ok());
+    /// assert!(Shell::sh().strict(true).run("false; echo still running").is_err());
+    /// assert!(Shell::sh().strict(true).run("echo $UNSET_VARIABLE").is_err());
+    /// ```
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// The program of the shell.
+    pub fn program(&self) -> &str {
+        &self.program
+    }
+
+    /// A `CommandSpec` running `script` through this shell.
+    ///
+    /// Arguments added to the spec become the positional parameters `$1`, `$2`...
+    /// of the script, `$0` being the name of the shell.
+    pub fn command(&self, script: &str) -> CommandSpec {
+        let script = if self.strict {
+            format!("{}{}", STRICT_PRELUDE, script)
+        } else {
+            script.to_string()
+        };
+        CommandSpec::new(&self.program).args(["-c", script.as_str(), self.program.as_str()])
+    }
+
+    /// Run `script` through this shell, checked like `Execute::run`.
+    pub fn run(&self, script: &str) -> Result<ExecOutput, CommandError> {
+        self.command(script).run()
+    }
+}
@@ -0,0 +1,147 @@
+//! # Shellwords module
+//! Split a command line into words the way a POSIX shell would, and the inverse:
+//! quote words so a command line can be rendered safely (in logs for instance).
+//!
+//! Only quoting is understood: single quotes, double quotes and backslash escapes.
+//! Nothing is expanded, `*`, `$HOME`, `~` or `#` are kept as they are.
+
+use std::borrow::Cow;
+use std::fmt;
+
+/// Why a command line couldn't be split into words.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitError {
+    /// a `'` or `"` was opened and never closed
+    UnterminatedQuote(char),
+    /// the line ends with a lone `\`
+    TrailingBackslash,
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitError::UnterminatedQuote(q) => write!(f, "missing closing quote {}", q),
+            SplitError::TrailingBackslash => write!(f, "the line ends with an escape character"),
+        }
+    }
+}
+
+impl std::error::Error for SplitError {}
+
+/// Split `line` into words following the POSIX shell quoting rules.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::shellwords::split;
+///
+/// let words = split(r#"git log --format='%h %s' "a \"b\"" c\ d $HOME"#).unwrap();
+/// assert_eq!(words, ["git", "log", "--format=%h %s", "a \"b\"", "c d", "$HOME"]);
+/// ```
+pub fn split(line: &str) -> Result<Vec<String>, SplitError> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // a word can be empty but present, like `''`
+    let mut in_word = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(SplitError::UnterminatedQuote('\'')),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        // inside double quotes a backslash only escapes these
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('$' | '`' | '"' | '\\')) => word.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(SplitError::UnterminatedQuote('"')),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(SplitError::UnterminatedQuote('"')),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                // a line continuation
+                Some('\n') => {}
+                Some(c) => {
+                    in_word = true;
+                    word.push(c);
+                }
+                None => return Err(SplitError::TrailingBackslash),
+            },
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Quote `word` so a POSIX shell reads it back as a single, unchanged word.
+///
+/// Words made of safe characters only are returned as they are.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::shellwords::quote;
+///
+/// assert_eq!(quote("--max-depth=1"), "--max-depth=1");
+/// assert_eq!(quote("%h %s"), "'%h %s'");
+/// assert_eq!(quote("it's"), r"'it'\''s'");
+/// assert_eq!(quote(""), "''");
+/// ```
+pub fn quote(word: &str) -> Cow<'_, str> {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-+=:,./@%".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        return Cow::Borrowed(word);
+    }
+    Cow::Owned(format!("'{}'", word.replace('\'', r"'\''")))
+}
+
+/// Quote every word and join them with spaces, the inverse of `split`.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::shellwords::{join, split};
+///
+/// let line = join(["echo", "two words", "$HOME"]);
+/// assert_eq!(line, "echo 'two words' '$HOME'");
+/// assert_eq!(split(&line).unwrap(), ["echo", "two words", "$HOME"]);
+/// ```
+pub fn join<I, S>(words: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    words
+        .into_iter()
+        .map(|word| quote(word.as_ref()).into_owned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,377 @@
+//! # Tasks module
+//! A graph of named commands depending on each other (fetch → build → test, lint
+//! next to them...). Every task starts as soon as its dependencies succeeded,
+//! independent tasks run in parallel, and the tasks depending on a failed one are skipped.
+
+use crate::color::Col;
+use crate::error::CommandError;
+use crate::execute::CommandSpec;
+use crate::output::ExecOutput;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A named command and the names of the tasks it depends on.
+#[derive(Debug, Clone)]
+pub struct Task {
+    name: String,
+    spec: CommandSpec,
+    depends_on: Vec<String>,
+}
+
+impl Task {
+    /// A task named `name` running `spec`.
+    pub fn new(name: impl Into<String>, spec: CommandSpec) -> Self {
+        Task {
+            name: name.into(),
+            spec,
+            depends_on: Vec::new(),
+        }
+    }
+
+    /// Only start this task once all of `tasks` succeeded.
+    pub fn depends_on<I, S>(mut self, tasks: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.depends_on.extend(tasks.into_iter().map(Into::into));
+        self
+    }
+
+    /// The name of the task.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Why a task graph can't be run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphError {
+    /// two tasks have the same name
+    DuplicateTask(String),
+    /// a task depends on a task that doesn't exist
+    UnknownDependency { task: String, dependency: String },
+    /// the tasks depend on each other in a loop, the names follow the loop
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::DuplicateTask(name) => write!(f, "task '{}' is defined twice", name),
+            GraphError::UnknownDependency { task, dependency } => {
+                write!(
+                    f,
+                    "task '{}' depends on unknown task '{}'",
+                    task, dependency
+                )
+            }
+            GraphError::Cycle(names) => write!(f, "dependency cycle: {}", names.join(" -> ")),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+/// # TaskGraph
+/// A set of tasks scheduled in dependency order.
+///
+/// ## Example
+/// ```rust
+/// use commandcrafter::execute::CommandSpec;
+/// use commandcrafter::tasks::{Task, TaskGraph};
+///
+/// let report = TaskGraph::new()
+///     .push(Task::new("fetch", CommandSpec::new("echo").arg("fetched")))
+///     .push(Task::new("build", CommandSpec::new("false")).depends_on(["fetch"]))
+///     .push(Task::new("test", CommandSpec::new("echo").arg("tested")).depends_on(["build"]))
+///     .push(Task::new("lint", CommandSpec::new("echo").arg("linted")))
+///     .run()
+///     .unwrap();
+/// report.print_summary();
+/// assert!(report.get("fetch").unwrap().is_ok());
+/// assert!(report.get("build").unwrap().is_err());
+/// assert!(report.get("lint").unwrap().is_ok());
+/// assert!(!report.all_succeeded());
+/// ```
+#[derive(Debug, Clone)]
+pub struct TaskGraph {
+    tasks: Vec<Task>,
+    limit: usize,
+}
+
+/// What happened to every task of a graph.
+#[derive(Debug)]
+pub struct GraphReport {
+    /// the name and result of every task, in the order they were added;
+    /// tasks skipped because a dependency failed hold `CommandError::Skipped`
+    pub results: Vec<(String, Result<ExecOutput, CommandError>)>,
+    /// the wall-clock time the whole graph took
+    pub elapsed: Duration,
+}
+
+impl Default for TaskGraph {
+    fn default() -> Self {
+        TaskGraph::new()
+    }
+}
+
+impl TaskGraph {
+    /// An empty graph running as many tasks at a time as there are CPUs.
+    pub fn new() -> Self {
+        TaskGraph {
+            tasks: Vec::new(),
+            limit: thread::available_parallelism().map_or(4, |n| n.get()),
+        }
+    }
+
+    /// Add a task to the graph, its dependencies may be added later.
+    pub fn push(mut self, task: Task) -> Self {
+        self.tasks.push(task);
+        self
+    }
+
+    /// Run at most `limit` tasks at the same time (at least one).
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit.max(1);
+        self
+    }
+
+    /// Check that names are unique, dependencies exist and there is no cycle.
+    ///
+    /// Returns the dependencies of every task as indexes into the task list.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use commandcrafter::execute::CommandSpec;
+    /// use commandcrafter::tasks::{GraphError, Task, TaskGraph};
+    ///
+    /// let graph = TaskGraph::new()
+    ///     .push(Task::new("a", CommandSpec::new("true")).depends_on(["b"]))
+    ///     .push(Task::new("b", CommandSpec::new("true")).depends_on(["a"]));
+    /// assert_eq!(
+    ///     graph.validate().unwrap_err(),
+    ///     GraphError::Cycle(vec!["a".into(), "b".into(), "a".into()])
+    /// );
+    /// ```
+    pub fn validate(&self) -> Result<Vec<Vec<usize>>, GraphError> {
+        let mut index = HashMap::new();
+        for (i, task) in self.tasks.iter().enumerate() {
+            if index.insert(task.name.as_str(), i).is_some() {
+                return Err(GraphError::DuplicateTask(task.name.clone()));
+            }
+        }
+        let mut deps = Vec::with_capacity(self.tasks.len());
+        for task in &self.tasks {
+            let mut ids = Vec::new();
+            for dep in &task.depends_on {
+                match index.get(dep.as_str()) {
+                    Some(&i) => ids.push(i),
+                    None => {
+                        return Err(GraphError::UnknownDependency {
+                            task: task.name.clone(),
+                            dependency: dep.clone(),
+                        })
+                    }
+                }
+            }
+            deps.push(ids);
+        }
+        if let Some(cycle) = find_cycle(&deps) {
+            let names = cycle.iter().map(|&i| self.tasks[i].name.clone()).collect();
+            return Err(GraphError::Cycle(names));
+        }
+        Ok(deps)
+    }
+
+    /// Execute the graph: each task starts once its dependencies succeeded,
+    /// and the tasks downstream of a failure are skipped.
+    pub fn run(&self) -> Result<GraphReport, GraphError> {
+        let deps = self.validate()?;
+        let started = Instant::now();
+        let count = self.tasks.len();
+        let mut results: Vec<Option<Result<ExecOutput, CommandError>>> =
+            (0..count).map(|_| None).collect();
+        let mut waiting_on: Vec<usize> = deps.iter().map(Vec::len).collect();
+        let mut dependents = vec![Vec::new(); count];
+        for (task, task_deps) in deps.iter().enumerate() {
+            for &dep in task_deps {
+                dependents[dep].push(task);
+            }
+        }
+        let mut ready: Vec<usize> = (0..count).filter(|&i| waiting_on[i] == 0).collect();
+        // the lowest index first, so tasks start in the order they were added
+        ready.reverse();
+        let mut done = 0;
+        let mut running = 0;
+
+        let (tx, rx) = mpsc::channel();
+        thread::scope(|scope| {
+            while done < count {
+                while running < self.limit {
+                    let Some(i) = ready.pop() else { break };
+                    let tx = tx.clone();
+                    let spec = &self.tasks[i].spec;
+                    scope.spawn(move || {
+                        let _ = tx.send((i, spec.run()));
+                    });
+                    running += 1;
+                }
+                let Ok((i, result)) = rx.recv() else { break };
+                running -= 1;
+                done += 1;
+                if result.is_ok() {
+                    for &next in &dependents[i] {
+                        waiting_on[next] -= 1;
+                        if waiting_on[next] == 0 {
+                            ready.push(next);
+                        }
+                    }
+                    ready.sort_unstable_by(|a, b| b.cmp(a));
+                } else {
+                    done += skip_downstream(i, &dependents, &self.tasks, &mut results);
+                }
+                results[i] = Some(result);
+            }
+        });
+
+        let results = self
+            .tasks
+            .iter()
+            .zip(results)
+            .map(|(task, result)| {
+                let result = result.unwrap_or_else(|| {
+                    Err(CommandError::Skipped {
+                        program: task.spec.get_program().to_string(),
+                    })
+                });
+                (task.name.clone(), result)
+            })
+            .collect();
+        Ok(GraphReport {
+            results,
+            elapsed: started.elapsed(),
+        })
+    }
+}
+
+/// mark every task depending (transitively) on `failed` as skipped, returns how many were marked
+fn skip_downstream(
+    failed: usize,
+    dependents: &[Vec<usize>],
+    tasks: &[Task],
+    results: &mut [Option<Result<ExecOutput, CommandError>>],
+) -> usize {
+    let mut skipped = 0;
+    let mut stack = dependents[failed].clone();
+    while let Some(i) = stack.pop() {
+        if results[i].is_some() {
+            continue;
+        }
+        results[i] = Some(Err(CommandError::Skipped {
+            program: tasks[i].spec.get_program().to_string(),
+        }));
+        skipped += 1;
+        stack.extend(&dependents[i]);
+    }
+    skipped
+}
+
+/// depth-first search for a cycle, returned as the tasks along it with the first one repeated
+fn find_cycle(deps: &[Vec<usize>]) -> Option<Vec<usize>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        New,
+        InProgress,
+        Done,
+    }
+    fn visit(i: usize, deps: &[Vec<usize>], marks: &mut [Mark], path: &mut Vec<usize>) -> bool {
+        marks[i] = Mark::InProgress;
+        path.push(i);
+        for &dep in &deps[i] {
+            let found = match marks[dep] {
+                Mark::InProgress => {
+                    let start = path.iter().position(|&p| p == dep).unwrap_or(0);
+                    path.drain(..start);
+                    path.push(dep);
+                    true
+                }
+                Mark::New => visit(dep, deps, marks, path),
+                Mark::Done => false,
+            };
+            if found {
+                return true;
+            }
+        }
+        path.pop();
+        marks[i] = Mark::Done;
+        false
+    }
+
+    let mut marks = vec![Mark::New; deps.len()];
+    for i in 0..deps.len() {
+        let mut path = Vec::new();
+        if marks[i] == Mark::New && visit(i, deps, &mut marks, &mut path) {
+            return Some(path);
+        }
+    }
+    None
+}
+
+impl GraphReport {
+    /// The result of the task named `name`.
+    pub fn get(&self, name: &str) -> Option<&Result<ExecOutput, CommandError>> {
+        self.results
+            .iter()
+            .find(|(task, _)| task == name)
+            .map(|(_, result)| result)
+    }
+
+    /// `true` when every task succeeded.
+    pub fn all_succeeded(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+
+    /// A table with the status, duration and exit code of every task, colored with `Col`.
+    pub fn summary(&self) -> String {
+        let width = self
+            .results
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max(4);
+        let mut table = format!(
+            "{:<width$}  {:<9}  {:>10}  {}\n",
+            "TASK", "STATUS", "TIME", "CODE"
+        );
+        for (name, result) in &self.results {
+            let (status, color, output) = match result {
+                Ok(out) => ("ok", Col::Green, Some(out)),
+                Err(CommandError::Skipped { .. }) => ("skipped", Col::Yellow, None),
+                Err(e) => ("failed", Col::Red, e.output()),
+            };
+            let time = output.map_or("-".to_string(), |out| format!("{:.2?}", out.duration));
+            let code = output
+                .and_then(|out| out.code())
+                .map_or("-".to_string(), |code| code.to_string());
+            table += &format!(
+                "{:<width$}  {}  {:>10}  {}\n",
+                name,
+                color.print_col(&format!("{:<9}", status)),
+                time,
+                code
+            );
+        }
+        table += &format!("total {:.2?}", self.elapsed);
+        table
+    }
+
+    /// Print the summary table into the console.
+    pub fn print_summary(&self) {
+        println!("{}", self.summary());
+    }
+}
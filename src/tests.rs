@@ -1,60 +1,416 @@
 #![cfg(test)]
 
-use super::execute::Execute;
+use super::error::CommandError;
+use super::execute::{CommandSpec, Execute};
+use super::filestore::write_output;
+use super::runner::{Expectation, MockRunner, Runner};
 
 #[test]
 /// this test is intends to execute a command
 fn execute_command() {
-    let output: Vec<u8> = Execute::new("pwd", &["--version"]);
-    assert_eq!(output, Execute::new("pwd", &["--version"]));
+    let runner = MockRunner::new().expect(
+        Expectation::new("pwd")
+            .args(["--version"])
+            .stdout("pwd (GNU coreutils) 9.4\n"),
+    );
+    let output = runner.run_args("pwd", &["--version"]).unwrap();
+    assert_eq!(output.stdout_str(), "pwd (GNU coreutils) 9.4\n");
+    assert_eq!(output.command, "pwd --version");
+    runner.verify();
 }
+
 #[test]
 fn execute_command_error() {
-    let output: Vec<u8> = Execute::new("ls", &["-ll"]);
-    assert_ne!(output, Execute::new("pwd", &["--version"]));
+    let runner = MockRunner::new().expect(
+        Expectation::new("ls")
+            .args(["-ll"])
+            .stderr("ls: invalid option\n")
+            .exit_code(2),
+    );
+    match runner.run_args("ls", &["-ll"]) {
+        Err(CommandError::NonZeroExit { code, stderr, .. }) => {
+            assert_eq!(code, 2);
+            assert_eq!(stderr, "ls: invalid option\n");
+        }
+        other => panic!("unexpected {:?}", other),
+    }
 }
 
 #[test]
-/// this test is intends to execute a command and store it inside a file `ExecuteLog.log`
-pub fn store_inside_file() {
-    let output = Execute::new("pwd", &["--version"]);
-    let res = Execute::write_to_file(&output);
-    assert_eq!(res.is_ok(), true);
+/// this test is intends to store the output of a command the way the log files do
+fn store_output() {
+    let runner = MockRunner::new().expect(Expectation::new("pwd").stdout("/home\n"));
+    let output = runner.run_args("pwd", &[]);
+    let mut log = Vec::new();
+    write_output(&mut log, &output).unwrap();
+    assert_eq!(String::from_utf8(log).unwrap(), "$ pwd\n/home\n");
 }
 
 #[test]
-#[should_panic]
-/// this test is intends to execute a wrong command and without storing it inside a file `ExecuteLog.log`
+/// a missing program is reported as such and logged with its error
 fn wrong_cmd() {
-    let output = Execute::new("pw", &["--ver"]);
-    assert_eq!(output, output);
+    let runner = MockRunner::new().expect(Expectation::new("pw").not_found());
+    let output = runner.run_args("pw", &["--ver"]);
+    assert!(matches!(output, Err(CommandError::NotFound { ref program }) if program == "pw"));
+    let mut log = Vec::new();
+    write_output(&mut log, &output).unwrap();
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        "Error: command 'pw' not found\n"
+    );
 }
 
 #[test]
-#[should_panic]
-/// this test is intends to execute a wrong command and without storing it inside a file `ExecuteLog.log`
-fn store_inside_file_error_wrong_cmd() {
-    // use wrong commands
-    let output = Execute::new("tre", &["--ver"]);
-    let res = Execute::write_to_file(&output);
-    assert_eq!(res.is_err(), true);
+/// the output of a failing command is kept in the error
+fn output_of_failure() {
+    let runner = MockRunner::new().expect(
+        Expectation::new("make")
+            .stdout("building\n")
+            .stderr("boom\n")
+            .exit_code(1),
+    );
+    let err = runner.run(&CommandSpec::new("make")).unwrap_err();
+    let output = err.output().unwrap();
+    assert_eq!(output.stdout_str(), "building\n");
+    assert!(!output.success());
+    // `output` doesn't check the status
+    let runner = MockRunner::new().expect(Expectation::new("false").exit_code(1));
+    assert_eq!(
+        runner.output(&CommandSpec::new("false")).unwrap().code(),
+        Some(1)
+    );
+}
+
+#[test]
+/// the expectations are matched in order, each one for as many calls as it wants
+fn expectations_in_order() {
+    let runner = MockRunner::new()
+        .expect(Expectation::new("curl").exit_code(7).times(2))
+        .expect(Expectation::new("curl").stdout("ok"));
+    assert!(runner.run_args("curl", &["example.org"]).is_err());
+    assert!(runner.run_args("curl", &["example.org"]).is_err());
+    assert!(runner.run_args("curl", &["example.org"]).is_ok());
+    runner.verify();
+}
+
+#[test]
+fn specs_are_recorded() {
+    let runner = MockRunner::new().expect(Expectation::new("env"));
+    let spec = CommandSpec::new("env").cwd("/tmp").env("LANG", "C");
+    runner.run(&spec).unwrap();
+    let specs = runner.specs();
+    assert_eq!(specs[0].get_cwd(), Some(std::path::Path::new("/tmp")));
+    assert_eq!(specs[0].get_envs(), [("LANG".into(), Some("C".into()))]);
+}
+
+#[test]
+#[should_panic(expected = "unexpected command 'rm -rf /'")]
+fn unexpected_call() {
+    let runner = MockRunner::new().expect(Expectation::new("ls"));
+    let _ = runner.run_args("rm", &["-rf", "/"]);
+}
+
+#[test]
+#[should_panic(expected = "called 1 of 2 times")]
+fn missing_call() {
+    let runner = MockRunner::new().expect(Expectation::new("ls").times(2));
+    runner.run_args("ls", &[]).unwrap();
+    runner.verify();
+}
+
+#[test]
+/// code written against the trait object takes the mock like the real runner
+fn runner_trait_objects() {
+    fn greet(runner: &dyn Runner) -> String {
+        let out = runner.run_args("echo", &["hello"]).unwrap();
+        out.stdout_str().into_owned()
+    }
+    let mock = MockRunner::new().expect(Expectation::new("echo").stdout("mocked\n"));
+    assert_eq!(greet(&mock), "mocked\n");
 }
 
 #[test]
 /// this test check the operation of existence of the file log
 fn check_op() {
-    let out = Execute::new("ls", &["-l"]);
-    let res = Execute::write_to_file(&out);
-    let bl = Execute::check_operation(&res);
-    assert_eq!(bl, true)
+    assert!(Execute::check_operation(&Ok(())));
 }
 
 #[test]
 #[should_panic]
 /// check if the operation of existence of the file log is not exists and should panic accordingly to that.
 fn check_op_with_error() {
-    let out = Execute::new("tre", &["--ver"]);
-    let res = Execute::write_to_file(&out);
-    let bl = Execute::check_operation(&res);
-    assert_eq!(bl, false);
+    let res = Err(CommandError::NotFound {
+        program: "tre".into(),
+    });
+    Execute::check_operation(&res);
+}
+
+#[test]
+/// a recorded session replays without calling the runner again
+fn cassette_record_then_replay() {
+    use super::cassette::{Cassette, CassetteMode};
+
+    let fixture = std::env::temp_dir().join(format!("cassette-test-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&fixture);
+    let mock = MockRunner::new().expect(
+        Expectation::new("pacman")
+            .args(["-Qu"])
+            .stdout("linux 6.9.1-1 -> 6.9.2-1\n")
+            .times(1),
+    );
+    let spec = CommandSpec::new("pacman").arg("-Qu").env("LANG", "C");
+    let cassette = Cassette::with_runner(&fixture, CassetteMode::RecordIfMissing, &mock).unwrap();
+    let recorded = cassette.run(&spec).unwrap();
+    let replayed = cassette.run(&spec).unwrap();
+    assert_eq!(recorded.stdout, replayed.stdout);
+    mock.verify();
+
+    let cassette =
+        Cassette::with_runner(&fixture, CassetteMode::Replay, MockRunner::new()).unwrap();
+    let replayed = cassette.run(&spec).unwrap();
+    assert_eq!(replayed.stdout_str(), "linux 6.9.1-1 -> 6.9.2-1\n");
+    // the environment is part of the match
+    assert!(cassette
+        .run(&CommandSpec::new("pacman").arg("-Qu"))
+        .is_err());
+    let _ = std::fs::remove_file(&fixture);
+}
+
+#[test]
+/// the pieces going the same way are joined, every line gets the prefix of its direction
+fn transcript_lines() {
+    use super::interactive::{Direction, Transcript};
+
+    let mut transcript = Transcript::new("pacman -S linux");
+    transcript.push(
+        Direction::Received,
+        b"resolving dependencies...\n:: Proceed",
+    );
+    transcript.push(Direction::Received, b" with installation? [Y/n] ");
+    transcript.push(Direction::Sent, b"y\n");
+    transcript.push(Direction::Received, b"installing linux\n");
+    assert_eq!(transcript.entries().len(), 3);
+    assert_eq!(
+        transcript.to_string(),
+        "$ pacman -S linux\n< resolving dependencies...\n< :: Proceed with installation? [Y/n] \n> y\n< installing linux\n"
+    );
+}
+
+#[test]
+/// a task can store its log in a folder of its own
+fn task_log_dir() {
+    use super::config::CrafterConfig;
+    use super::filestore::Filestore;
+
+    let config = CrafterConfig::parse(
+        "[tasks.a]\ncommand = \"true\"\nlog_dir = \"/var/log/crafter\"\n[tasks.b]\ncommand = \"true\"\n",
+    )
+    .unwrap();
+    let store = config.tasks["a"].filestore();
+    assert_eq!(store.dir(), std::path::Path::new("/var/log/crafter"));
+    assert_eq!(
+        store.path("a.log"),
+        std::path::Path::new("/var/log/crafter/a.log")
+    );
+    assert_eq!(config.tasks["b"].filestore(), Filestore::default());
+}
+
+#[test]
+/// the program of the command names the log, without anything that could leave the folder
+fn log_name_template() {
+    use super::filestore::{command_name, render_filename};
+    use std::path::Path;
+
+    assert_eq!(command_name("/usr/bin/pacman -Syu"), "pacman");
+    assert_eq!(command_name("'my tool' --flag"), "my_tool");
+    assert_eq!(command_name("'../..' x"), "command");
+    assert_eq!(command_name("'.hidden;rm -rf'"), "hidden_rm_-rf");
+    assert_eq!(command_name(""), "command");
+    let path = render_filename(
+        Path::new("{date}/{command}-{pid}.log"),
+        "make all",
+        Some(42),
+    );
+    let date = path.parent().unwrap().to_str().unwrap();
+    assert_eq!(date.len(), "2024-05-31".len());
+    assert_eq!(path.file_name().unwrap(), "make-42.log");
+    assert_eq!(
+        render_filename(Path::new("build.log"), "make", Some(42)),
+        Path::new("build.log")
+    );
+}
+
+#[test]
+/// the cancel handle of a command still stops it inside a fail-fast batch
+fn own_cancel_in_batch() {
+    use super::batch::Batch;
+    use super::execute::CancelHandle;
+    use std::time::{Duration, Instant};
+
+    let handle = CancelHandle::new();
+    let trigger = handle.clone();
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(200));
+        trigger.cancel();
+    });
+    let started = Instant::now();
+    let out = Batch::new()
+        .fail_fast(true)
+        .push(CommandSpec::new("sleep").arg("2").cancel_on(&handle))
+        .run();
+    canceller.join().unwrap();
+    assert!(matches!(
+        out.results[0],
+        Err(CommandError::Cancelled { .. })
+    ));
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[cfg(feature = "async")]
+#[test]
+/// `run_async` keeps the cancel handle of a command too
+fn own_cancel_in_async_batch() {
+    use super::batch::Batch;
+    use super::execute::CancelHandle;
+    use std::time::{Duration, Instant};
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let handle = CancelHandle::new();
+    let trigger = handle.clone();
+    let started = Instant::now();
+    let out = runtime.block_on(async move {
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            trigger.cancel();
+        });
+        Batch::new()
+            .fail_fast(true)
+            .push(CommandSpec::new("sleep").arg("2").cancel_on(&handle))
+            .run_async()
+            .await
+    });
+    assert!(matches!(
+        out.results[0],
+        Err(CommandError::Cancelled { .. })
+    ));
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[cfg(feature = "async")]
+#[test]
+/// an endless stdin source is streamed to the command, not read up front
+fn endless_stdin_async() {
+    use super::execute::Input;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let out = runtime
+        .block_on(
+            CommandSpec::new("head")
+                .args(["-c", "5"])
+                .stdin(Input::reader(std::io::repeat(b'y')))
+                .timeout(std::time::Duration::from_secs(5))
+                .run_async(),
+        )
+        .unwrap();
+    assert_eq!(out.stdout_str(), "yyyyy");
+}
+
+#[test]
+/// a stage that can't be spawned stops the ones already running
+fn pipeline_spawn_failure() {
+    use super::execute::Pipeline;
+    use std::time::{Duration, Instant};
+
+    let started = Instant::now();
+    let res = Pipeline::new(CommandSpec::new("sleep").arg("3"))
+        .pipe(CommandSpec::new("no-such-binary-for-crafter"))
+        .output();
+    assert!(matches!(res, Err(CommandError::NotFound { .. })));
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+/// cancelling a command waiting for its next attempt ends the wait, without spawning it again
+fn cancel_during_backoff() {
+    use super::batch::Batch;
+    use super::retry::{Backoff, RetryPolicy};
+    use std::time::{Duration, Instant};
+
+    let started = Instant::now();
+    let out = Batch::new()
+        .limit(2)
+        .fail_fast(true)
+        .push(
+            CommandSpec::new("sh")
+                .args(["-c", "echo flaky >&2; exit 1"])
+                .retry(RetryPolicy::new(3).backoff(Backoff::Fixed(Duration::from_secs(2)))),
+        )
+        .push(CommandSpec::new("sh").args(["-c", "sleep 0.2; exit 3"]))
+        .run();
+    assert!(started.elapsed() < Duration::from_secs(1));
+    match &out.results[0] {
+        Err(CommandError::Cancelled { output }) => {
+            assert_eq!(output.stderr_str(), "flaky\n");
+            assert!(output.attempts.is_empty());
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[cfg(feature = "async")]
+#[test]
+/// `run_async` stops waiting for the next attempt once cancelled too
+fn cancel_during_backoff_async() {
+    use super::execute::CancelHandle;
+    use super::retry::{Backoff, RetryPolicy};
+    use std::time::{Duration, Instant};
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let handle = CancelHandle::new();
+    let trigger = handle.clone();
+    let started = Instant::now();
+    let res = runtime.block_on(async move {
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            trigger.cancel();
+        });
+        CommandSpec::new("false")
+            .retry(RetryPolicy::new(3).backoff(Backoff::Fixed(Duration::from_secs(2))))
+            .cancel_on(&handle)
+            .run_async()
+            .await
+    });
+    assert!(matches!(res, Err(CommandError::Cancelled { .. })));
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+/// a stdin reader that never yields doesn't keep the run waiting once the command exited
+fn stdin_reader_never_yields() {
+    use super::execute::Input;
+    use std::time::Duration;
+
+    let (reader, writer) = std::io::pipe().unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let res = CommandSpec::new("true")
+            .stdin(Input::reader(reader))
+            .timeout(Duration::from_secs(1))
+            .run();
+        let _ = tx.send(res);
+    });
+    let res = rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("the run returns");
+    assert!(res.is_ok());
+    drop(writer);
 }
@@ -0,0 +1,51 @@
+//! Run the `commandcrafter` binary the way a shell would.
+
+use std::process::Command;
+
+fn commandcrafter(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_commandcrafter"))
+        .args(args)
+        .env("COMMANDCRAFTER_CONFIG", "/nonexistent/Crafter.toml")
+        .output()
+        .unwrap()
+}
+
+#[test]
+/// a timeout too long to represent is a usage error, not a crash
+fn timeout_out_of_range() {
+    let out = commandcrafter(&["--timeout", "99999999999999999999999s", "run", "true"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("too long"));
+}
+
+#[test]
+/// `logs show` only reads inside the log folder
+fn log_outside_the_folder() {
+    for name in ["../../etc/hostname", "/etc/hostname"] {
+        let out = commandcrafter(&["logs", "show", name]);
+        assert_eq!(out.status.code(), Some(1));
+        assert!(out.stdout.is_empty());
+    }
+}
+
+#[test]
+/// a broken task file only fails the targets that could be tasks
+fn broken_task_file() {
+    let config = std::env::temp_dir().join(format!("broken-crafter-{}.toml", std::process::id()));
+    std::fs::write(&config, "[tasks.x\n").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_commandcrafter"))
+            .args(args)
+            .env("COMMANDCRAFTER_CONFIG", &config)
+            .output()
+            .unwrap()
+    };
+    let out = run(&["--quiet", "run", "echo", "ok"]);
+    assert_eq!(out.status.code(), Some(0));
+    let out = run(&["--quiet", "run", "true"]);
+    assert_eq!(out.status.code(), Some(0));
+    let out = run(&["run", "not-a-program-or-task"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("invalid task file"));
+    let _ = std::fs::remove_file(&config);
+}